@@ -0,0 +1,79 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::events::transaction::Transaction;
+
+/// Remembers the `(transaction_hash, event_index)` of the last `capacity` received events, so that an event sent
+/// twice, e.g. when a read is retried after a partial failure, is only counted once.
+pub(crate) struct DuplicateFilter {
+    capacity: usize,
+    /// Received events by order of reception, with their block number.
+    received: VecDeque<(u64, (String, u64))>,
+    keys: HashSet<(String, u64)>
+}
+
+impl DuplicateFilter {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { capacity, received: VecDeque::with_capacity(capacity), keys: HashSet::with_capacity(capacity) }
+    }
+
+    /// Returns `false` when the event of `transaction` has already been received.
+    pub(crate) fn insert(&mut self, transaction: &Transaction) -> bool {
+        let key = (transaction.transaction_hash.clone(), transaction.event_index);
+        if !self.keys.insert(key.clone()) {
+            return false;
+        }
+        if self.received.len() == self.capacity {
+            if let Some((_, oldest)) = self.received.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        self.received.push_back((transaction.block_number, key));
+        true
+    }
+
+    /// Forgets the events received from the blocks after `fork_block`, which are sent again once retracted.
+    pub(crate) fn rollback(&mut self, fork_block: u64) {
+        while self.received.back().is_some_and(|(block_number, _)| *block_number > fork_block) {
+            if let Some((_, key)) = self.received.pop_back() {
+                self.keys.remove(&key);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::DuplicateFilter;
+    use crate::events::{source::scripted_transaction, transaction::Transaction};
+
+    fn event(block_number: u64, timestamp: u64, event_index: u64) -> Transaction {
+        Transaction { block_number, event_index, ..scripted_transaction("BTC/USD", timestamp, 100) }
+    }
+
+    #[rstest]
+    fn duplicate_filter_drops_events_received_twice() {
+        let mut filter = DuplicateFilter::new(2);
+        assert!(filter.insert(&event(1, 10, 0)));
+        assert!(filter.insert(&event(1, 10, 1)));
+        assert!(!filter.insert(&event(1, 10, 0)));
+
+        // The oldest event leaves the window.
+        assert!(filter.insert(&event(2, 20, 0)));
+        assert!(filter.insert(&event(1, 10, 0)));
+        assert!(!filter.insert(&event(2, 20, 0)));
+    }
+
+    #[rstest]
+    fn duplicate_filter_forgets_retracted_blocks() {
+        let mut filter = DuplicateFilter::new(10);
+        assert!(filter.insert(&event(1, 10, 0)));
+        assert!(filter.insert(&event(2, 20, 0)));
+
+        filter.rollback(1);
+        assert!(filter.insert(&event(2, 20, 0)));
+        assert!(!filter.insert(&event(1, 10, 0)));
+    }
+}
@@ -0,0 +1,203 @@
+use std::{fs, path::{Path, PathBuf}, sync::Mutex, time::SystemTime};
+
+use serde::Deserialize;
+use tokio::sync::mpsc::Receiver;
+
+use crate::events::{
+    failover::ProviderStatus,
+    source::{BlockHeader, EventSource},
+    spot_entry::SpotEntry,
+    transaction::Transaction
+};
+
+/// Names compared without case. An empty `allow` list allows every name not denied.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AllowDenyList {
+    allow: Vec<String>,
+    deny: Vec<String>
+}
+
+impl AllowDenyList {
+    fn accepts(&self, name: &str) -> bool {
+        let contains = |names: &[String]| names.iter().any(|listed| listed.eq_ignore_ascii_case(name));
+        !contains(&self.deny) && (self.allow.is_empty() || contains(&self.allow))
+    }
+}
+
+/// Which publishers and sources may contribute to the TWAP, e.g.
+/// `{ "publishers": { "deny": ["FOO"] }, "sources": { "allow": ["BINANCE", "OKX"] } }`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct EntryFilter {
+    publishers: AllowDenyList,
+    sources: AllowDenyList
+}
+
+impl EntryFilter {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        serde_json::from_str(&content).map_err(|e| format!("Failed to parse the entry filter {}: {e}", path.display()))
+    }
+
+    pub(crate) fn accepts(&self, entry: &SpotEntry) -> bool {
+        self.publishers.accepts(&entry.publisher) && self.sources.accepts(&entry.source)
+    }
+}
+
+/// The filter file and the filter loaded from it the last time it was modified.
+struct WatchedFilter {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    filter: EntryFilter
+}
+
+/// Wraps a source to drop the entries of the publishers and sources excluded by an `EntryFilter`.
+/// The filter file is read again whenever it is modified, so that a publisher can be excluded without a restart.
+pub(crate) struct FilteredSource<S> {
+    source: S,
+    watched: Option<Mutex<WatchedFilter>>
+}
+
+impl<S: EventSource> FilteredSource<S> {
+    /// Every entry is accepted when no filter file is given.
+    pub(crate) fn new(source: S, path: Option<PathBuf>) -> Result<Self, String> {
+        let watched = match path {
+            Some(path) => {
+                let modified = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+                let filter = EntryFilter::load(&path)?;
+                Some(Mutex::new(WatchedFilter { path, modified, filter }))
+            }
+            None => None
+        };
+        Ok(Self { source, watched })
+    }
+
+    /// Reloads the filter when its file has been modified, keeping the previous one if it is invalid.
+    fn filter(&self) -> EntryFilter {
+        let Some(watched) = &self.watched else {
+            return EntryFilter::default();
+        };
+        let mut watched = watched.lock().unwrap();
+        let modified = fs::metadata(&watched.path).and_then(|metadata| metadata.modified()).ok();
+        if modified != watched.modified {
+            watched.modified = modified;
+            match EntryFilter::load(&watched.path) {
+                Ok(filter) => {
+                    println!("🔃 Reloaded the entry filter from {}", watched.path.display());
+                    watched.filter = filter;
+                }
+                Err(e) => eprintln!("⚠️ Keeping the previous entry filter : {e}")
+            }
+        }
+        watched.filter.clone()
+    }
+
+    /// Drops the transactions whose entry is not accepted by the filter.
+    fn accepted(&self, transactions: Vec<Transaction>) -> Vec<Transaction> {
+        let filter = self.filter();
+        transactions
+            .into_iter()
+            .filter(|transaction| {
+                let entry = &transaction.spot_entry;
+                let accepted = filter.accepts(entry);
+                if !accepted {
+                    println!(
+                        "🚫 [block:{}] Ignoring the {} entry of {} from {}",
+                        transaction.block_number, entry.pair_id, entry.publisher, entry.source
+                    );
+                }
+                accepted
+            })
+            .collect()
+    }
+}
+
+impl<S: EventSource> EventSource for FilteredSource<S> {
+    async fn block_number(&self) -> Result<u64, String> {
+        self.source.block_number().await
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        self.source.block_header(block_number).await
+    }
+
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        Ok(self.accepted(self.source.get_transactions(from_block, to_block).await?))
+    }
+
+    async fn get_pending_transactions(&self, block_number: u64) -> Result<Vec<Transaction>, String> {
+        Ok(self.accepted(self.source.get_pending_transactions(block_number).await?))
+    }
+
+    async fn finalized_block_number(&self, latest_block: u64) -> Result<u64, String> {
+        self.source.finalized_block_number(latest_block).await
+    }
+
+    fn providers(&self) -> Vec<ProviderStatus> {
+        self.source.providers()
+    }
+
+    async fn subscribe_new_heads(&self) -> Result<Receiver<u64>, String> {
+        self.source.subscribe_new_heads().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::{Duration, SystemTime}};
+
+    use rstest::rstest;
+
+    use super::{EntryFilter, FilteredSource};
+    use crate::events::{
+        source::{scripted_transaction, EventSource, ScriptedSource},
+        transaction::Transaction
+    };
+
+    fn published_by(publisher: &str, source: &str, price: u128) -> Transaction {
+        let mut transaction = scripted_transaction("BTC/USD", price as u64, price);
+        transaction.spot_entry.publisher = String::from(publisher);
+        transaction.spot_entry.source = String::from(source);
+        transaction
+    }
+
+    #[rstest]
+    #[case(r#"{}"#, "ANY", "ANY", true)]
+    #[case(r#"{ "publishers": { "deny": ["evil"] } }"#, "EVIL", "OKX", false)]
+    #[case(r#"{ "publishers": { "deny": ["EVIL"] } }"#, "PRAGMA", "OKX", true)]
+    #[case(r#"{ "sources": { "allow": ["BINANCE", "OKX"] } }"#, "PRAGMA", "OKX", true)]
+    #[case(r#"{ "sources": { "allow": ["BINANCE", "OKX"] } }"#, "PRAGMA", "KRAKEN", false)]
+    #[case(r#"{ "sources": { "allow": ["OKX"], "deny": ["OKX"] } }"#, "PRAGMA", "OKX", false)]
+    fn entry_filter_accepts(#[case] config: &str, #[case] publisher: &str, #[case] source: &str, #[case] expected: bool) {
+        let filter: EntryFilter = serde_json::from_str(config).unwrap();
+        assert_eq!(filter.accepts(&published_by(publisher, source, 0).spot_entry), expected);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn filtered_source_reloads_modified_filter() {
+        let path = std::env::temp_dir().join(format!("twaplast-entry-filter-{}.json", std::process::id()));
+        fs::write(&path, r#"{ "publishers": { "deny": ["EVIL"] } }"#).unwrap();
+
+        let source = ScriptedSource::new();
+        source.push_block(vec![published_by("EVIL", "OKX", 1), published_by("PRAGMA", "OKX", 2)]);
+
+        let filtered = FilteredSource::new(source, Some(path.clone())).unwrap();
+        let prices = |transactions: Vec<Transaction>| transactions.iter().map(|t| t.spot_entry.price).collect::<Vec<_>>();
+        assert_eq!(prices(filtered.get_transactions(0, 1).await.unwrap()), vec![2]);
+
+        fs::write(&path, r#"{ "publishers": { "deny": ["PRAGMA"] } }"#).unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert_eq!(prices(filtered.get_transactions(0, 1).await.unwrap()), vec![1]);
+
+        // An invalid filter is ignored until fixed.
+        fs::write(&path, "{ not json").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(120)).unwrap();
+        assert_eq!(prices(filtered.get_transactions(0, 1).await.unwrap()), vec![1]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,352 @@
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{keccak256, Address, B256, I256, U256},
+    providers::{Provider, ProviderBuilder, ReqwestProvider},
+    rpc::{client::RpcClient, types::{BlockTransactionsKind, Filter, Log}},
+    transports::http::{reqwest::Client, Http}
+};
+
+use crate::events::{
+    source::{BlockHeader, EventSource, RPC_TIMEOUT},
+    spot_entry::SpotEntry,
+    transaction::Transaction
+};
+
+/// Signature of the event emitted by the Chainlink aggregators on each new answer.
+pub(crate) const ANSWER_UPDATED: &str = "AnswerUpdated(int256,uint256,uint256)";
+
+/// Source of the entries read from an EVM chain.
+const EVM_SOURCE: &str = "EVM";
+
+/// Connection to an EVM JSON-RPC endpoint, shared by the sources reading the logs of its contracts.
+pub(crate) struct EvmChain {
+    provider: ReqwestProvider
+}
+
+impl EvmChain {
+    /// Connects to `rpc_url` and checks that a contract is deployed at `address`.
+    pub(crate) async fn connect(rpc_url: &str, address: &str) -> Result<(Self, Address), String> {
+        let address: Address = address.parse().map_err(|e| format!("Invalid EVM address {address} : {e}"))?;
+        let url = rpc_url.parse().map_err(|e| format!("Invalid URL {rpc_url} : {e}"))?;
+        let client = Client::builder()
+            .timeout(RPC_TIMEOUT)
+            .build()
+            .map_err(|e| format!("Failed to build the HTTP client : {e}"))?;
+        let transport = Http::with_client(client, url);
+        let is_local = transport.guess_local();
+        let provider = ProviderBuilder::new().on_client(RpcClient::new(transport, is_local));
+
+        let code = provider
+            .get_code_at(address)
+            .await
+            .map_err(|e| format!("Failed to get the code of {address} : {e}"))?;
+        if code.is_empty() {
+            return Err(format!("No contract is deployed at {address}"));
+        }
+        println!("✅ The EVM contract {address} has been found");
+        Ok((Self { provider }, address))
+    }
+
+    pub(crate) fn provider(&self) -> &ReqwestProvider {
+        &self.provider
+    }
+
+    pub(crate) async fn block_number(&self) -> Result<u64, String> {
+        self.provider.get_block_number().await.map_err(|e| format!("Failed to get block number {e}"))
+    }
+
+    pub(crate) async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        let block = self
+            .provider
+            .get_block_by_number(BlockNumberOrTag::Number(block_number), BlockTransactionsKind::Hashes)
+            .await
+            .map_err(|e| format!("Failed to get block {block_number} {e}"))?
+            .ok_or(format!("Unknown block {block_number}"))?;
+        Ok(BlockHeader {
+            block_number,
+            block_hash: block.header.hash.to_string(),
+            parent_hash: block.header.parent_hash.to_string(),
+            timestamp: block.header.timestamp
+        })
+    }
+
+    /// The logs of the event `signature` emitted by `address` between `from_block` and `to_block` (both included).
+    pub(crate) async fn get_logs(
+        &self,
+        address: Address,
+        signature: B256,
+        from_block: u64,
+        to_block: u64
+    ) -> Result<Vec<Log>, String> {
+        let filter =
+            Filter::new().address(address).event_signature(signature).from_block(from_block).to_block(to_block);
+        self.provider.get_logs(&filter).await.map_err(|e| format!("Failed to fetch logs {e}"))
+    }
+}
+
+/// Decodes the price updates of `logs` with `decode`, skipping the malformed ones.
+/// Each log is indexed by its canonical index in its block, whatever the filter and pages it was fetched with.
+pub(crate) fn logs_to_transactions<F>(logs: &[Log], mut decode: F) -> Vec<Transaction>
+where
+    F: FnMut(&Log, u64) -> Result<Transaction, String>
+{
+    logs.iter()
+        .filter_map(|log| {
+            log.log_index
+                .ok_or_else(|| String::from("The log has no index"))
+                .and_then(|event_index| decode(log, event_index))
+                .inspect_err(|e| eprintln!("⚠️ Skipping the malformed log of {:?} : {e}", log.transaction_hash))
+                .ok()
+        })
+        .collect()
+}
+
+/// Reads the price updates logged by an EVM contract, by default the `AnswerUpdated` events of a Chainlink aggregator.
+/// The logged event must index the price as its first argument and hold the update timestamp as its first
+/// non-indexed one, as `AnswerUpdated(int256 indexed current, uint256 indexed roundId, uint256 updatedAt)` does.
+/// Every entry is attributed to `pair_id`, the aggregator being its publisher.
+pub(crate) struct EvmSource {
+    chain: EvmChain,
+    address: Address,
+    event_signature: B256,
+    pair_id: String
+}
+
+/// Decodes the price update of `log`.
+fn log_to_transaction(log: &Log, pair_id: &str, event_index: u64) -> Result<Transaction, String> {
+    let price_topic = log.topics().get(1).ok_or("The log does not index the price")?;
+    let price = I256::from_raw(U256::from_be_bytes(price_topic.0));
+    if price.is_negative() {
+        return Err(format!("Negative price {price}"));
+    }
+    let price: u128 = price.into_raw().try_into().map_err(|_| format!("The price {price} does not fit in 128 bits"))?;
+
+    let data = &log.data().data;
+    let timestamp_word = data.get(..32).ok_or("The log holds no update timestamp")?;
+    let timestamp = U256::from_be_slice(timestamp_word);
+    let timestamp: u64 = timestamp.try_into().map_err(|_| format!("Invalid update timestamp {timestamp}"))?;
+
+    Ok(Transaction {
+        block_number: log.block_number.ok_or("The log is not in a mined block")?,
+        transaction_hash: log.transaction_hash.ok_or("The log has no transaction hash")?.to_string(),
+        event_index,
+        from_address: log.address().to_string(),
+        spot_entry: SpotEntry {
+            timestamp,
+            source: String::from(EVM_SOURCE),
+            publisher: log.address().to_string(),
+            price,
+            pair_id: String::from(pair_id),
+            volume: 0
+        }
+    })
+}
+
+impl EvmSource {
+    /// Connects to `rpc_url` and checks that a contract is deployed at `address`.
+    pub(crate) async fn connect(rpc_url: &str, address: &str, pair_id: &str) -> Result<Self, String> {
+        let (chain, address) = EvmChain::connect(rpc_url, address).await?;
+        Ok(Self { chain, address, event_signature: keccak256(ANSWER_UPDATED), pair_id: String::from(pair_id) })
+    }
+
+    /// Reads the logs of the event `signature`, e.g. `AnswerUpdated(int256,uint256,uint256)`, instead.
+    pub(crate) fn with_event_signature(self, signature: &str) -> Self {
+        Self { event_signature: keccak256(signature), ..self }
+    }
+}
+
+impl EventSource for EvmSource {
+    async fn block_number(&self) -> Result<u64, String> {
+        self.chain.block_number().await
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        self.chain.block_header(block_number).await
+    }
+
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        let logs = self.chain.get_logs(self.address, self.event_signature, from_block, to_block).await?;
+        Ok(logs_to_transactions(&logs, |log, event_index| log_to_transaction(log, &self.pair_id, event_index)))
+    }
+}
+
+#[cfg(test)]
+pub(crate) use mock::{block_hash, mock_evm_rpc, word, MockEvmChain};
+
+#[cfg(test)]
+mod mock {
+    use alloy::primitives::U256;
+    use axum::{extract::State, routing::post, Json, Router};
+    use serde_json::{json, Value};
+
+    /// What the mock RPC knows of the chain: its logs, the latest block being the last one holding a log, and the
+    /// results of the contract calls as `(to, input, result)`.
+    #[derive(Clone, Default)]
+    pub(crate) struct MockEvmChain {
+        pub(crate) logs: Vec<Value>,
+        pub(crate) calls: Vec<(String, String, String)>
+    }
+
+    pub(crate) fn word(value: U256) -> String {
+        format!("0x{}", alloy::hex::encode(value.to_be_bytes::<32>()))
+    }
+
+    pub(crate) fn block_hash(block_number: u64) -> String {
+        word(U256::from(block_number + 1))
+    }
+
+    fn hex_number(value: &Value) -> u64 {
+        u64::from_str_radix(value.as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+    }
+
+    /// Answers the JSON-RPC calls of the EVM sources like an anvil node would.
+    async fn mock_rpc(State(chain): State<MockEvmChain>, Json(request): Json<Value>) -> Json<Value> {
+        let head = chain.logs.iter().map(|log| hex_number(&log["blockNumber"])).max().unwrap_or(0);
+        let params = &request["params"];
+        let result = match request["method"].as_str().unwrap() {
+            "eth_getCode" => json!("0x6080"),
+            "eth_blockNumber" => json!(format!("0x{head:x}")),
+            "eth_getBlockByNumber" => {
+                let block_number = hex_number(&params[0]);
+                let zero = word(U256::ZERO);
+                json!({
+                    "number": format!("0x{block_number:x}"),
+                    "hash": block_hash(block_number),
+                    "parentHash": block_number.checked_sub(1).map_or(zero.clone(), block_hash),
+                    "sha3Uncles": zero,
+                    "miner": "0x0000000000000000000000000000000000000000",
+                    "stateRoot": zero,
+                    "transactionsRoot": zero,
+                    "receiptsRoot": zero,
+                    "logsBloom": format!("0x{}", "00".repeat(256)),
+                    "difficulty": "0x0",
+                    "gasLimit": "0x0",
+                    "gasUsed": "0x0",
+                    "timestamp": format!("0x{:x}", 1_700_000_000 + block_number * 12),
+                    "extraData": "0x",
+                    "mixHash": zero,
+                    "nonce": "0x0000000000000000",
+                    "transactions": [],
+                    "uncles": []
+                })
+            }
+            "eth_getLogs" => {
+                let (from_block, to_block) = (hex_number(&params[0]["fromBlock"]), hex_number(&params[0]["toBlock"]));
+                let signature = &params[0]["topics"][0];
+                json!(chain
+                    .logs
+                    .iter()
+                    .filter(|log| (from_block..=to_block).contains(&hex_number(&log["blockNumber"])))
+                    .filter(|log| log["topics"][0] == *signature)
+                    .collect::<Vec<_>>())
+            }
+            "eth_call" => {
+                let call = &params[0];
+                let to = call["to"].as_str().unwrap();
+                let input = call["input"].as_str().or(call["data"].as_str()).unwrap();
+                let (_, _, result) = chain
+                    .calls
+                    .iter()
+                    .find(|(called, called_input, _)| called.eq_ignore_ascii_case(to) && called_input == input)
+                    .unwrap_or_else(|| panic!("Unexpected call of {input} to {to}"));
+                json!(result)
+            }
+            method => panic!("Unexpected call to {method}")
+        };
+        Json(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+    }
+
+    /// Serves `chain` over HTTP and returns its RPC URL.
+    pub(crate) async fn mock_evm_rpc(chain: MockEvmChain) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route("/", post(mock_rpc)).with_state(chain);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{keccak256, I256, U256};
+    use rstest::rstest;
+    use serde_json::{json, Value};
+
+    use super::{
+        block_hash, log_to_transaction, logs_to_transactions, mock_evm_rpc, word, EvmSource, MockEvmChain, ANSWER_UPDATED
+    };
+    use crate::events::source::EventSource;
+
+    const AGGREGATOR: &str = "0x5f4ec3df9cbd43714fe2740f5e3616155c5b8419";
+
+    fn answer_updated(block_number: u64, price: I256, updated_at: u64) -> Value {
+        json!({
+            "address": AGGREGATOR,
+            "topics": [keccak256(ANSWER_UPDATED).to_string(), word(price.into_raw()), word(U256::from(block_number))],
+            "data": word(U256::from(updated_at)),
+            "blockNumber": format!("0x{block_number:x}"),
+            "blockHash": block_hash(block_number),
+            "transactionHash": word(U256::from(1_000 + block_number)),
+            "transactionIndex": "0x0",
+            "logIndex": "0x0",
+            "removed": false
+        })
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn evm_source_reads_answer_updated_logs() {
+        let url = mock_evm_rpc(MockEvmChain {
+            logs: vec![
+                answer_updated(1, I256::try_from(6_500_000_000_000i128).unwrap(), 1_700_000_010),
+                answer_updated(2, I256::try_from(-1).unwrap(), 1_700_000_020),
+                answer_updated(3, I256::try_from(6_600_000_000_000i128).unwrap(), 1_700_000_030)
+            ],
+            calls: vec![]
+        }).await;
+
+        let source = EvmSource::connect(&url, AGGREGATOR, "ETH/USD").await.unwrap();
+        assert_eq!(source.block_number().await, Ok(3));
+
+        let header = source.block_header(2).await.unwrap();
+        assert_eq!((header.block_hash, header.parent_hash), (block_hash(2), block_hash(1)));
+
+        // The negative answer is skipped.
+        let transactions = source.get_transactions(0, 3).await.unwrap();
+        let entries: Vec<(u64, u64, u128, &str)> = transactions
+            .iter()
+            .map(|t| (t.block_number, t.spot_entry.timestamp, t.spot_entry.price, t.spot_entry.pair_id.as_str()))
+            .collect();
+        assert_eq!(entries, vec![
+            (1, 1_700_000_010, 6_500_000_000_000, "ETH/USD"),
+            (3, 1_700_000_030, 6_600_000_000_000, "ETH/USD")
+        ]);
+        assert_eq!(source.get_transactions(2, 2).await.unwrap(), vec![]);
+
+        // Another signature matches none of the logged events.
+        let source = source.with_event_signature("NewRound(uint256,address,uint256)");
+        assert_eq!(source.get_transactions(0, 3).await.unwrap(), vec![]);
+    }
+
+    #[rstest]
+    fn logs_to_transactions_indexes_logs_by_their_log_index() {
+        let log = |block_number: u64, log_index: u64| {
+            let mut log = answer_updated(block_number, I256::ONE, 1_700_000_000);
+            log["logIndex"] = json!(format!("0x{log_index:x}"));
+            serde_json::from_value(log).unwrap()
+        };
+        let mut without_index = answer_updated(2, I256::ONE, 1_700_000_000);
+        without_index["logIndex"] = Value::Null;
+
+        let logs = vec![log(1, 7), log(1, 3), serde_json::from_value(without_index).unwrap(), log(2, 0)];
+        let indexes = |logs: &[_]| -> Vec<u64> {
+            logs_to_transactions(logs, |log, event_index| log_to_transaction(log, "ETH/USD", event_index))
+                .iter()
+                .map(|transaction| transaction.event_index)
+                .collect()
+        };
+        assert_eq!(indexes(&logs), vec![7, 3, 0]);
+        assert_eq!(indexes(&logs[1..2]), vec![3]);
+    }
+}
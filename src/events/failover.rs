@@ -0,0 +1,252 @@
+use std::sync::Mutex;
+
+use futures_util::future::join_all;
+use serde::Serialize;
+use tokio::sync::mpsc::Receiver;
+
+use crate::events::{source::{BlockHeader, EventSource}, transaction::Transaction};
+
+/// Health of one provider of a `FailoverSource`, as served by the REST API.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct ProviderStatus {
+    pub(crate) name: String,
+    pub(crate) is_active: bool,
+    pub(crate) block_number: Option<u64>,
+    pub(crate) consecutive_failures: u32,
+    pub(crate) total_failures: u64,
+    pub(crate) total_requests: u64
+}
+
+impl ProviderStatus {
+    pub(crate) fn new(name: String, is_active: bool) -> Self {
+        Self {
+            name,
+            is_active,
+            block_number: None,
+            consecutive_failures: 0,
+            total_failures: 0,
+            total_requests: 0
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.consecutive_failures == 0
+    }
+
+    pub(crate) fn record<T>(&mut self, result: &Result<T, String>) {
+        self.total_requests += 1;
+        match result {
+            Ok(_) => self.consecutive_failures = 0,
+            Err(e) => {
+                self.consecutive_failures += 1;
+                self.total_failures += 1;
+                eprintln!("⚠️ The RPC provider {} failed : {e}", self.name);
+            }
+        }
+    }
+}
+
+/// Sends every call to the active provider of an ordered list, switching to the next healthy one when it errors or
+/// when its block height lags more than `max_lag` blocks behind the highest one.
+pub(crate) struct FailoverSource<S> {
+    providers: Vec<S>,
+    statuses: Mutex<Vec<ProviderStatus>>,
+    max_lag: u64
+}
+
+impl<S: EventSource> FailoverSource<S> {
+    /// `providers` are given by order of preference, with the name used in the logs and the metrics.
+    pub(crate) fn new(providers: Vec<(String, S)>, max_lag: u64) -> Self {
+        let (names, providers): (Vec<String>, Vec<S>) = providers.into_iter().unzip();
+        let statuses = names.into_iter().enumerate().map(|(index, name)| ProviderStatus::new(name, index == 0)).collect();
+        Self { providers, statuses: Mutex::new(statuses), max_lag }
+    }
+
+    fn active(&self) -> usize {
+        self.statuses.lock().unwrap().iter().position(|status| status.is_active).unwrap_or(0)
+    }
+
+    fn set_active(&self, index: usize) {
+        let mut statuses = self.statuses.lock().unwrap();
+        if !statuses[index].is_active {
+            println!("🔀 Switching the RPC provider to {}", statuses[index].name);
+        }
+        for (i, status) in statuses.iter_mut().enumerate() {
+            status.is_active = i == index;
+        }
+    }
+
+    /// The active provider first, then the healthy ones by order of preference.
+    fn candidates(&self) -> Vec<usize> {
+        let active = self.active();
+        let statuses = self.statuses.lock().unwrap();
+        std::iter::once(active)
+            .chain((0..statuses.len()).filter(|&index| index != active && statuses[index].is_healthy()))
+            .collect()
+    }
+
+    fn record<T>(&self, index: usize, result: &Result<T, String>) {
+        self.statuses.lock().unwrap()[index].record(result);
+    }
+}
+
+impl<S: EventSource> EventSource for FailoverSource<S> {
+    /// Queries every provider to track their health and height, then elects the first healthy provider that is not
+    /// lagging behind.
+    async fn block_number(&self) -> Result<u64, String> {
+        let results = join_all(self.providers.iter().map(|provider| provider.block_number())).await;
+
+        let highest = {
+            let mut statuses = self.statuses.lock().unwrap();
+            for (status, result) in statuses.iter_mut().zip(&results) {
+                if let Ok(block_number) = result {
+                    status.block_number = Some(*block_number);
+                }
+            }
+            results.iter().filter_map(|result| result.as_ref().ok()).max().copied()
+        };
+        for (index, result) in results.iter().enumerate() {
+            self.record(index, result);
+        }
+
+        let Some(highest) = highest else {
+            let errors: Vec<String> = results.into_iter().filter_map(Result::err).collect();
+            return Err(format!("Every RPC provider failed : {}", errors.join(", ")));
+        };
+        let elected = results
+            .iter()
+            .position(|result| result.as_ref().is_ok_and(|block_number| block_number + self.max_lag >= highest))
+            .unwrap_or(0);
+        self.set_active(elected);
+        results[elected].clone()
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        let mut last_error = String::from("No RPC provider");
+        for index in self.candidates() {
+            let result = self.providers[index].block_header(block_number).await;
+            self.record(index, &result);
+            match result {
+                Ok(header) => {
+                    self.set_active(index);
+                    return Ok(header);
+                }
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        let mut last_error = String::from("No RPC provider");
+        for index in self.candidates() {
+            let result = self.providers[index].get_transactions(from_block, to_block).await;
+            self.record(index, &result);
+            match result {
+                Ok(transactions) => {
+                    self.set_active(index);
+                    return Ok(transactions);
+                }
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn get_pending_transactions(&self, block_number: u64) -> Result<Vec<Transaction>, String> {
+        let mut last_error = String::from("No RPC provider");
+        for index in self.candidates() {
+            let result = self.providers[index].get_pending_transactions(block_number).await;
+            self.record(index, &result);
+            match result {
+                Ok(transactions) => return Ok(transactions),
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn finalized_block_number(&self, latest_block: u64) -> Result<u64, String> {
+        let mut last_error = String::from("No RPC provider");
+        for index in self.candidates() {
+            let result = self.providers[index].finalized_block_number(latest_block).await;
+            self.record(index, &result);
+            match result {
+                Ok(block_number) => return Ok(block_number),
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+
+    fn providers(&self) -> Vec<ProviderStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    /// Subscribes through the first candidate supporting it; the events are still read from the active provider.
+    async fn subscribe_new_heads(&self) -> Result<Receiver<u64>, String> {
+        let mut last_error = String::from("No RPC provider");
+        for index in self.candidates() {
+            match self.providers[index].subscribe_new_heads().await {
+                Ok(receiver) => return Ok(receiver),
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::FailoverSource;
+    use crate::events::source::{EventSource, ScriptedSource};
+
+    fn sources(heights: [u64; 2]) -> (ScriptedSource, ScriptedSource, FailoverSource<ScriptedSource>) {
+        let (primary, secondary) = (ScriptedSource::new(), ScriptedSource::new());
+        for (source, height) in [(&primary, heights[0]), (&secondary, heights[1])] {
+            for _ in 0..height {
+                source.push_block(vec![]);
+            }
+        }
+        let failover = FailoverSource::new(
+            vec![(String::from("primary"), primary.clone()), (String::from("secondary"), secondary.clone())],
+            2
+        );
+        (primary, secondary, failover)
+    }
+
+    fn active_name(failover: &FailoverSource<ScriptedSource>) -> String {
+        failover.providers().into_iter().find(|status| status.is_active).unwrap().name
+    }
+
+    #[rstest]
+    #[case([10, 11], Ok(10), "primary")]
+    #[case([10, 13], Ok(13), "secondary")]
+    #[tokio::test]
+    async fn block_number_elects_provider_not_lagging(
+        #[case] heights: [u64; 2],
+        #[case] expected: Result<u64, String>,
+        #[case] expected_active: &str
+    ) {
+        let (_, _, failover) = sources(heights);
+        assert_eq!(failover.block_number().await, expected);
+        assert_eq!(active_name(&failover), expected_active);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn failing_provider_is_replaced_then_restored() {
+        let (primary, _, failover) = sources([5, 5]);
+
+        primary.fail_next_calls(1);
+        assert!(failover.block_header(3).await.is_ok());
+        assert_eq!(active_name(&failover), "secondary");
+        assert_eq!(failover.providers()[0].total_failures, 1);
+
+        assert_eq!(failover.block_number().await, Ok(5));
+        assert_eq!(active_name(&failover), "primary");
+    }
+}
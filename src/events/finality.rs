@@ -0,0 +1,153 @@
+use std::{collections::VecDeque, fmt, future::Future, str::FromStr};
+
+use crate::events::transaction::Transaction;
+
+/// How final a block must be before its events count towards the finalised TWAP.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Finality {
+    /// The block is followed by at least this many blocks, 0 making every accepted block final.
+    Confirmations(u64),
+    /// The state of the block has been accepted on L1.
+    AcceptedOnL1
+}
+
+impl Default for Finality {
+    fn default() -> Self {
+        Finality::Confirmations(0)
+    }
+}
+
+impl fmt::Display for Finality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finality::Confirmations(confirmations) => write!(f, "{confirmations}"),
+            Finality::AcceptedOnL1 => write!(f, "l1")
+        }
+    }
+}
+
+/// Either a number of confirmations or `l1`.
+impl FromStr for Finality {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("l1") || value.eq_ignore_ascii_case("accepted_on_l1") {
+            return Ok(Finality::AcceptedOnL1);
+        }
+        value
+            .parse()
+            .map(Finality::Confirmations)
+            .map_err(|_| format!("Expected a number of confirmations or `l1` but got {value}"))
+    }
+}
+
+/// Searches the last block accepted on L1 up to `latest_block`, `from_block` being known to be accepted already.
+/// The blocks are accepted on L1 in order, so only a logarithmic number of blocks is checked.
+pub(crate) async fn last_accepted_on_l1<F, Fut>(
+    from_block: u64,
+    latest_block: u64,
+    mut is_accepted_on_l1: F
+) -> Result<u64, String>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = Result<bool, String>>
+{
+    let (mut accepted, mut not_accepted) = (from_block.min(latest_block), latest_block + 1);
+    while not_accepted - accepted > 1 {
+        let middle = accepted + (not_accepted - accepted) / 2;
+        if is_accepted_on_l1(middle).await? {
+            accepted = middle;
+        } else {
+            not_accepted = middle;
+        }
+    }
+    Ok(accepted)
+}
+
+/// The received events whose block is not final yet, by order of reception.
+#[derive(Default)]
+pub(crate) struct FinalityBuffer {
+    transactions: VecDeque<Transaction>
+}
+
+impl FinalityBuffer {
+    pub(crate) fn push(&mut self, transaction: Transaction) {
+        self.transactions.push_back(transaction);
+    }
+
+    /// Removes and returns the events of the blocks up to `block_number`, now final.
+    pub(crate) fn finalize(&mut self, block_number: u64) -> Vec<Transaction> {
+        let finalized = self
+            .transactions
+            .iter()
+            .position(|transaction| transaction.block_number > block_number)
+            .unwrap_or(self.transactions.len());
+        self.transactions.drain(..finalized).collect()
+    }
+
+    /// Forgets the events of the blocks after `fork_block`.
+    pub(crate) fn rollback(&mut self, fork_block: u64) {
+        self.transactions.retain(|transaction| transaction.block_number <= fork_block);
+    }
+
+    pub(crate) fn transactions(&self) -> Vec<Transaction> {
+        self.transactions.iter().cloned().collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use rstest::rstest;
+
+    use super::{last_accepted_on_l1, Finality, FinalityBuffer};
+    use crate::events::{source::scripted_transaction, transaction::Transaction};
+
+    #[rstest]
+    #[case("12", Ok(Finality::Confirmations(12)))]
+    #[case("L1", Ok(Finality::AcceptedOnL1))]
+    #[case("accepted_on_l1", Ok(Finality::AcceptedOnL1))]
+    #[case("-1", Err(String::from("Expected a number of confirmations or `l1` but got -1")))]
+    fn finality_from_str(#[case] value: &str, #[case] expected: Result<Finality, String>) {
+        assert_eq!(value.parse::<Finality>(), expected);
+    }
+
+    #[rstest]
+    #[case(0, 100, 57)]
+    #[case(10, 100, 57)]
+    #[case(57, 100, 57)]
+    #[case(0, 40, 40)]
+    #[tokio::test]
+    async fn last_accepted_on_l1_searches_the_boundary(
+        #[case] from_block: u64,
+        #[case] latest_block: u64,
+        #[case] expected: u64
+    ) {
+        let checked = RefCell::new(vec![]);
+        let result = last_accepted_on_l1(from_block, latest_block, |block_number| {
+            checked.borrow_mut().push(block_number);
+            async move { Ok(block_number <= 57) }
+        }).await;
+
+        assert_eq!(result, Ok(expected));
+        assert!(checked.borrow().len() <= 8);
+    }
+
+    #[rstest]
+    fn finality_buffer_releases_final_blocks() {
+        let in_block = |block_number: u64| Transaction { block_number, ..scripted_transaction("BTC/USD", block_number, 1) };
+        let mut buffer = FinalityBuffer::default();
+        for block_number in [1, 2, 2, 3, 4] {
+            buffer.push(in_block(block_number));
+        }
+
+        let blocks = |transactions: Vec<Transaction>| transactions.iter().map(|t| t.block_number).collect::<Vec<_>>();
+        assert_eq!(blocks(buffer.finalize(2)), vec![1, 2, 2]);
+        assert_eq!(blocks(buffer.finalize(2)), Vec::<u64>::new());
+
+        buffer.rollback(3);
+        assert_eq!(blocks(buffer.transactions()), vec![3]);
+    }
+}
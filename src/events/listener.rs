@@ -1,139 +1,288 @@
-use crate::events::transaction::Transaction;
-use starknet::{core::types::{BlockId, EventFilter, Felt}, providers::{jsonrpc::HttpTransport, JsonRpcClient, Provider, Url}};
+use std::time::{Duration, Instant};
+
+use crate::events::{reorg::ChainTracker, source::EventSource, transaction::Transaction};
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use crate::events::spot_entry::{felt_to_u128, felt_to_utf8_str, SpotEntry};
 
-const EVENT_HASH: &str = "0x280bb2099800026f90c334a3a23888ffe718a2920ffbbf4f44c6d3d5efb613c";
+/// Number of recent block hashes remembered to detect chain reorganisations.
+const TRACKED_BLOCKS: usize = 64;
+
+/// Maximum number of blocks read at once, so a long backfill progresses chunk by chunk.
+const MAX_BLOCKS_PER_REQUEST: u64 = 1000;
+
+/// Delay between two polls when no subscription to the new blocks is available.
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Delay between two polls while subscribed, in case a notification is lost.
+const SUBSCRIBED_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Delay before subscribing again once a subscription has been lost.
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(60);
 
-async fn read_and_send_events(
-    sender: &Sender<Transaction>,
-    provider: &JsonRpcClient<HttpTransport>,
-    filter: EventFilter,
-    number_of_blocks: u64,
-    target_pair_id: String,
+pub(crate) enum ListenerEvent {
+    Received(Transaction),
+    /// The blocks after `fork_block` have been orphaned: everything received from them must be retracted.
+    /// The canonical events of these blocks are sent again right after.
+    Reorg { fork_block: u64 },
+    /// Every event up to `block_number` (included) has been sent.
+    Processed { block_number: u64 },
+    /// The blocks up to `block_number` (included) have reached the required finality.
+    Finalized { block_number: u64 },
+    /// The events of the pending block, replacing the previously sent ones. They are sent again as `Received` once
+    /// their block is accepted.
+    Pending(Vec<Transaction>)
+}
+
+async fn read_and_send_events<S: EventSource>(
+    sender: &Sender<ListenerEvent>,
+    source: &S,
+    from_block: u64,
+    to_block: u64,
+    target_pair_ids: &[String],
     is_verbose: bool
-) {
-    match provider.get_events(filter, None, number_of_blocks*50).await {
-        Ok(events) => {
-            for event in events.events {
-                let pair_id = felt_to_utf8_str(event.data[4]).unwrap();
-                let received_block = event.block_number.unwrap();
-                
-                if pair_id != target_pair_id {
-                    println!("❔ [block:{received_block}] Did not received {target_pair_id} (but {pair_id})");
+) -> Result<(), String> {
+    match source.get_transactions(from_block, to_block).await {
+        Ok(transactions) => {
+            if is_verbose {
+                println!("📦 [block:{from_block}..={to_block}] Fetched {} events", transactions.len());
+            }
+            for transaction in transactions {
+                let pair_id = &transaction.spot_entry.pair_id;
+                let received_block = transaction.block_number;
+
+                if !target_pair_ids.contains(pair_id) {
+                    if is_verbose {
+                        println!("❔ [block:{received_block}] Ignoring an event of {pair_id}, which is not followed");
+                    }
                     continue;
                 }
 
-                let entry = SpotEntry {
-                    timestamp: event.data[0].to_biguint().to_u64_digits()[0],
-                    source: felt_to_utf8_str(event.data[1]).unwrap(),
-                    publisher: felt_to_utf8_str(event.data[2]).unwrap(),
-                    price: felt_to_u128(event.data[3]).unwrap(),
-                    pair_id,
-                    volume: felt_to_u128(event.data[5]).unwrap(),
-                };
-    
-                let transaction = Transaction {
-                    block_number: event.block_number.unwrap(),
-                    transaction_hash: event.transaction_hash.to_fixed_hex_string(),
-                    from_address: event.from_address.to_fixed_hex_string(),
-                    spot_entry: entry
-                };
-
                 if is_verbose {
                     println!("❕[block:{received_block}] Receive: {transaction}");
                 }
-                
-                sender.send(transaction).await.unwrap()
+
+                sender
+                    .send(ListenerEvent::Received(transaction))
+                    .await
+                    .map_err(|_| String::from("The receiver has been dropped"))?;
             }
+            Ok(())
         }
         Err(e) => {
-            eprintln!("❌ Failed to fetch events {e}");
+            eprintln!("❌ {e}");
+            Err(e)
         }
     }
 }
 
-pub(crate) async fn receive_event(
-    rpc_url: &str,
-    contract_addr: &str,
-    pair_id: &str,
-    max_iterations: Option<usize>,
-    is_verbose: bool,
-) -> Option<Receiver<Transaction>> {
-    if is_verbose {
-        println!("✅ Preparing to receive {pair_id} from {rpc_url} -> {contract_addr}");
+/// Follows the chain up to `to_block`, retracting the orphaned blocks when a reorganisation is detected.
+/// Returns the block from which the events must be read again, if any.
+async fn detect_reorg<S: EventSource>(
+    sender: &Sender<ListenerEvent>,
+    source: &S,
+    tracker: &mut ChainTracker,
+    from_block: u64,
+    to_block: u64
+) -> Option<u64> {
+    let mut earliest_fork: Option<u64> = None;
+    loop {
+        match tracker.follow(source, from_block, to_block).await {
+            Ok(Some(fork_block)) => {
+                println!("🔀 Chain reorganisation detected, rolling back to the block n°{fork_block}");
+                if sender.send(ListenerEvent::Reorg { fork_block }).await.is_err() {
+                    return earliest_fork;
+                }
+                earliest_fork = Some(earliest_fork.map_or(fork_block, |fork| fork.min(fork_block)));
+            }
+            Ok(None) => return earliest_fork,
+            Err(e) => {
+                eprintln!("❌ {e}");
+                return earliest_fork;
+            }
+        }
     }
-    let n_previous_block_to_retrieve: u64 = 20;
-    let contract_address = String::from(contract_addr);
-    let contract_address_felt = match Felt::from_hex(&contract_address) {
-        Ok(value) => value,
+}
+
+/// Sends the last final block among the processed ones, before `next_block`, when it differs from the `sent` one.
+async fn read_and_send_finalized<S: EventSource>(
+    sender: &Sender<ListenerEvent>,
+    source: &S,
+    next_block: u64,
+    sent: &mut Option<u64>
+) {
+    let Some(processed_block) = next_block.checked_sub(1) else {
+        return;
+    };
+    let block_number = match source.finalized_block_number(processed_block).await {
+        Ok(block_number) => block_number.min(processed_block),
+        Err(e) => {
+            eprintln!("⚠️ Failed to read the finalized block : {e}");
+            return;
+        }
+    };
+    if *sent != Some(block_number) && sender.send(ListenerEvent::Finalized { block_number }).await.is_ok() {
+        sent.replace(block_number);
+    }
+}
+
+/// Reads every block from `next_block` up to the latest one, so that no block is skipped even when several blocks
+/// are produced between two polls or when a call fails. Returns the next block to read.
+/// Long ranges are read in chunks of `MAX_BLOCKS_PER_REQUEST` blocks, each one being reported as processed, then the
+/// last final block is sent unless it is the `finalized` one, which is forgotten when orphaned.
+async fn catch_up<S: EventSource>(
+    sender: &Sender<ListenerEvent>,
+    source: &S,
+    tracker: &mut ChainTracker,
+    next_block: u64,
+    finalized: &mut Option<u64>,
+    target_pair_ids: &[String],
+    is_verbose: bool
+) -> u64 {
+    let block_number = match source.block_number().await {
+        Ok(block_number) => block_number,
         Err(e) => {
             eprintln!("❌ {e}");
-            return None;
+            return next_block;
         }
     };
-    let target_pair_id = String::from(pair_id);
 
-    let (sender, receiver) = mpsc::channel::<Transaction>(64);
+    let mut next_block = next_block;
+    if let Some(fork_block) = detect_reorg(sender, source, tracker, next_block, block_number).await {
+        next_block = next_block.min(fork_block + 1);
+        if finalized.is_some_and(|block_number| block_number > fork_block) {
+            *finalized = None;
+        }
+    }
 
-    let provider = JsonRpcClient::new(HttpTransport::new(
-        match Url::parse(rpc_url) {
-            Ok(url) => url,
-            Err(e) => {
-                eprintln!("{e}");
-                return None;
+    while next_block <= block_number {
+        let to_block = block_number.min(next_block + MAX_BLOCKS_PER_REQUEST - 1);
+        if read_and_send_events(sender, source, next_block, to_block, target_pair_ids, is_verbose).await.is_err() {
+            break;
+        }
+        if sender.send(ListenerEvent::Processed { block_number: to_block }).await.is_err() {
+            break;
+        }
+        next_block = to_block + 1;
+    }
+    read_and_send_finalized(sender, source, next_block, finalized).await;
+    next_block
+}
+
+/// Sends the events of the pending block when they differ from the `sent` ones.
+async fn read_and_send_pending<S: EventSource>(
+    sender: &Sender<ListenerEvent>,
+    source: &S,
+    block_number: u64,
+    target_pair_ids: &[String],
+    sent: &mut Vec<Transaction>
+) {
+    let transactions: Vec<Transaction> = match source.get_pending_transactions(block_number).await {
+        Ok(transactions) => transactions
+            .into_iter()
+            .filter(|transaction| target_pair_ids.contains(&transaction.spot_entry.pair_id))
+            .collect(),
+        Err(e) => {
+            eprintln!("⚠️ Failed to read the pending block : {e}");
+            return;
+        }
+    };
+    if transactions != *sent && sender.send(ListenerEvent::Pending(transactions.clone())).await.is_ok() {
+        *sent = transactions;
+    }
+}
+
+async fn subscribe<S: EventSource>(source: &S, is_verbose: bool) -> Option<Receiver<u64>> {
+    match source.subscribe_new_heads().await {
+        Ok(new_heads) => {
+            if is_verbose {
+                println!("📡 Subscribed to the new blocks");
             }
+            Some(new_heads)
         }
-    ));
-    
-    match provider.block_number().await {
-        Ok(block_number) => {
-            match provider.get_class_hash_at(BlockId::Number(block_number), contract_address_felt).await {
-                Ok(class_hash) => {
-                    if class_hash == Felt::ZERO {
-                        eprintln!("❌ The given contract address does not correspond to a deployed contract");
-                        return None;
-                    } else {
-                        println!("✅ The class contract retrieve with success : {:?}", class_hash);
-                    }
-                }
-                Err(e) => {
-                    eprintln!("❌ Failed to get contract class - the contract address might be wrong : {e}");
-                    return None;
-                }
+        Err(e) => {
+            if is_verbose {
+                println!("⏱️ Polling the new blocks, the subscription is unavailable : {e}");
             }
+            None
         }
+    }
+}
+
+/// Waits for the next block, notified by the subscription or polled when there is none.
+/// Returns `false` when the subscription has been lost.
+async fn wait_next_block(new_heads: Option<&mut Receiver<u64>>) -> bool {
+    let Some(new_heads) = new_heads else {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        return true;
+    };
+    tokio::select! {
+        head = new_heads.recv() => {
+            // Every pending notification is handled by the same catch up.
+            while new_heads.try_recv().is_ok() {}
+            head.is_some()
+        }
+        _ = tokio::time::sleep(SUBSCRIBED_POLL_INTERVAL) => true
+    }
+}
+
+/// Sends the events of the `pair_ids` from `from_block`, or from the last 20 blocks when not given, then follows the
+/// new blocks once the backfill is done.
+/// The new blocks are read as soon as the source notifies them, falling back to polling while it cannot.
+pub(crate) async fn receive_event<S: EventSource>(
+    source: S,
+    pair_ids: Vec<String>,
+    from_block: Option<u64>,
+    max_iterations: Option<usize>,
+    is_verbose: bool,
+) -> Option<Receiver<ListenerEvent>> {
+    if is_verbose {
+        println!("✅ Preparing to receive {pair_ids:?}");
+    }
+    let n_previous_block_to_retrieve: u64 = 20;
+
+    let (sender, receiver) = mpsc::channel::<ListenerEvent>(64);
+
+    let block_number = match source.block_number().await {
+        Ok(block_number) => block_number,
         Err(e) => {
-            eprintln!("❌ Failed to get block number - the contract address might be wrong : {e}");
+            eprintln!("❌ {e}");
             return None;
         }
     };
 
     tokio::spawn(async move {
         let mut iteration_count = 0;
-        let mut last_block_number: Option<u64> = None;
+        let mut tracker = ChainTracker::new(TRACKED_BLOCKS);
 
-        let block_number = provider.block_number().await.expect("Failed to get block number within loop");
-        let from_block = block_number - n_previous_block_to_retrieve;
-        last_block_number.replace(block_number);
+        let from_block = from_block.unwrap_or(block_number.saturating_sub(n_previous_block_to_retrieve));
         if is_verbose {
             println!("🧊 Last block number retrieve n°{block_number}");
             println!("🔄 Retrieve previous events from the block n°{from_block}");
         }
-        
-        let filter = EventFilter {
-            from_block: Some(BlockId::Number(from_block)),
-            to_block: Some(BlockId::Number(block_number)),
-            address: Some(contract_address_felt),
-            keys: Some(vec![vec![Felt::from_hex_unchecked(EVENT_HASH)]])
-        };
 
-        read_and_send_events(&sender, &provider, filter,block_number - from_block, target_pair_id.clone(), is_verbose).await;
+        let mut finalized = None;
+        let mut next_block =
+            catch_up(&sender, &source, &mut tracker, from_block, &mut finalized, &pair_ids, is_verbose).await;
+        let mut pending = vec![];
+        read_and_send_pending(&sender, &source, next_block, &pair_ids, &mut pending).await;
+        let mut new_heads = subscribe(&source, is_verbose).await;
+        let mut subscribed_at = Instant::now();
 
         loop {
-            tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+            if !wait_next_block(new_heads.as_mut()).await {
+                eprintln!("⚠️ The subscription to the new blocks was lost, falling back to polling");
+                new_heads = None;
+            }
+            if new_heads.is_none() && subscribed_at.elapsed() >= RESUBSCRIBE_INTERVAL {
+                new_heads = subscribe(&source, is_verbose).await;
+                subscribed_at = Instant::now();
+            }
 
-            let block_number = provider.block_number().await.expect("Failed to get block number within loop");
+            if sender.is_closed() {
+                if is_verbose {
+                    println!("❌ The receiver has been dropped, stopping the listener");
+                }
+                break;
+            }
 
             if let Some(limit) = max_iterations {
                 if iteration_count >= limit {
@@ -145,17 +294,9 @@ pub(crate) async fn receive_event(
                 iteration_count += 1;
             }
 
-            if last_block_number != Some(block_number) {
-                let filter = EventFilter {
-                    from_block: Some(BlockId::Number(block_number)),
-                    to_block: Some(BlockId::Number(block_number)),
-                    address: Some(contract_address_felt),
-                    keys: Some(vec![vec![Felt::from_hex_unchecked(EVENT_HASH)]])
-                };
-                read_and_send_events(&sender, &provider, filter,1, target_pair_id.clone(), is_verbose).await;
-            }
-
-            last_block_number.replace(block_number);
+            next_block =
+                catch_up(&sender, &source, &mut tracker, next_block, &mut finalized, &pair_ids, is_verbose).await;
+            read_and_send_pending(&sender, &source, next_block, &pair_ids, &mut pending).await;
         }
     });
 
@@ -166,38 +307,191 @@ pub(crate) async fn receive_event(
 
 #[cfg(test)]
 mod tests {
-    use rstest::{fixture, rstest};
+    use std::time::Duration;
 
-    use super::receive_event;
-    use std::env;
+    use rstest::rstest;
+    use tokio::{sync::mpsc::{self, Receiver}, time::timeout};
+
+    use super::{receive_event, ListenerEvent, MAX_BLOCKS_PER_REQUEST, POLL_INTERVAL};
+    use crate::events::{
+        source::{scripted_transaction, ScriptedSource},
+        websocket::{mock_websocket_server, MockWebSocket}
+    };
 
-    const RPC_BASE_URL: &str = "https://starknet-sepolia.infura.io/v3";
-    const CONTRACT_ADDR: &str = "0x036031daa264c24520b11d93af622c848b2499b66b41d611bac95e13cfca131a";
     const PAIR_ID: &str = "BTC/USD";
-    
-    #[fixture]
-    fn rpc_url() -> String {
-        let base_url = RPC_BASE_URL;
-        let infura_api_key = env::var("INFURA_API_KEY").expect("INFURA_API_KEY env var must be set");
-        format!("{}/{}", base_url, infura_api_key)
+
+    async fn next_price(receiver: &mut Receiver<ListenerEvent>) -> Option<(u64, u128)> {
+        loop {
+            match receiver.recv().await? {
+                ListenerEvent::Received(transaction) => {
+                    return Some((transaction.block_number, transaction.spot_entry.price))
+                }
+                ListenerEvent::Reorg { fork_block } => panic!("Unexpected reorg at {fork_block}"),
+                ListenerEvent::Processed { .. } | ListenerEvent::Finalized { .. } | ListenerEvent::Pending(_) => continue
+            }
+        }
+    }
+
+    async fn next_non_processed(receiver: &mut Receiver<ListenerEvent>) -> Option<ListenerEvent> {
+        loop {
+            match receiver.recv().await? {
+                ListenerEvent::Processed { .. } | ListenerEvent::Finalized { .. } => continue,
+                event => return Some(event)
+            }
+        }
+    }
+
+    async fn next_finalized(receiver: &mut Receiver<ListenerEvent>) -> Option<u64> {
+        loop {
+            match receiver.recv().await? {
+                ListenerEvent::Finalized { block_number } => return Some(block_number),
+                ListenerEvent::Reorg { fork_block } => panic!("Unexpected reorg at {fork_block}"),
+                _ => continue
+            }
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn receive_event_backfill_then_follow_new_blocks() {
+        let source = ScriptedSource::new();
+        source.push_block(vec![scripted_transaction(PAIR_ID, 10, 100), scripted_transaction("WBTC/USD", 11, 5)]);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 20, 200)]);
+
+        let mut receiver = receive_event(source.clone(), vec![String::from(PAIR_ID)], None, Some(2), false).await.unwrap();
+        assert_eq!(next_price(&mut receiver).await, Some((1, 100)));
+        assert_eq!(next_price(&mut receiver).await, Some((2, 200)));
+
+        source.push_block(vec![scripted_transaction(PAIR_ID, 30, 300)]);
+        assert_eq!(next_price(&mut receiver).await, Some((3, 300)));
+
+        // The listener stops after `max_iterations`, closing the channel.
+        assert!(next_non_processed(&mut receiver).await.is_none());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn receive_event_retracts_orphaned_blocks() {
+        let source = ScriptedSource::new();
+        source.push_block(vec![scripted_transaction(PAIR_ID, 10, 100)]);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 20, 200)]);
+
+        let mut receiver = receive_event(source.clone(), vec![String::from(PAIR_ID)], None, Some(1), false).await.unwrap();
+        assert_eq!(next_price(&mut receiver).await, Some((1, 100)));
+        assert_eq!(next_price(&mut receiver).await, Some((2, 200)));
+
+        source.reorg(1);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 21, 210)]);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 30, 300)]);
+
+        assert!(matches!(next_non_processed(&mut receiver).await, Some(ListenerEvent::Reorg { fork_block: 1 })));
+        assert_eq!(next_price(&mut receiver).await, Some((2, 210)));
+        assert_eq!(next_price(&mut receiver).await, Some((3, 300)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn receive_event_reads_every_block_produced_between_polls() {
+        let source = ScriptedSource::new();
+        source.push_block(vec![scripted_transaction(PAIR_ID, 10, 100)]);
+
+        let mut receiver = receive_event(source.clone(), vec![String::from(PAIR_ID)], None, Some(1), false).await.unwrap();
+        assert_eq!(next_price(&mut receiver).await, Some((1, 100)));
+        assert!(matches!(receiver.recv().await, Some(ListenerEvent::Processed { block_number: 1 })));
+
+        source.push_block(vec![scripted_transaction(PAIR_ID, 20, 200)]);
+        source.push_block(vec![]);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 40, 400)]);
+
+        assert_eq!(next_price(&mut receiver).await, Some((2, 200)));
+        assert_eq!(next_price(&mut receiver).await, Some((4, 400)));
+        assert!(matches!(receiver.recv().await, Some(ListenerEvent::Processed { block_number: 4 })));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn receive_event_sends_blocks_reaching_finality() {
+        let source = ScriptedSource::new();
+        source.require_confirmations(2);
+        for price in [100, 200, 300] {
+            source.push_block(vec![scripted_transaction(PAIR_ID, price as u64, price)]);
+        }
+
+        let mut receiver = receive_event(source.clone(), vec![String::from(PAIR_ID)], None, Some(2), false).await.unwrap();
+        assert_eq!(next_finalized(&mut receiver).await, Some(1));
+
+        source.push_block(vec![]);
+        assert_eq!(next_finalized(&mut receiver).await, Some(2));
     }
 
     #[rstest]
     #[tokio::test]
-    async fn receive_event_return_receiver_with_success(rpc_url: String) {
-        assert!(receive_event(&rpc_url, CONTRACT_ADDR, PAIR_ID, Some(1), true).await.is_some());
+    async fn receive_event_resumes_from_given_block() {
+        let source = ScriptedSource::new();
+        source.push_block(vec![scripted_transaction(PAIR_ID, 10, 100)]);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 20, 200)]);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 30, 300)]);
+
+        let mut receiver = receive_event(source.clone(), vec![String::from(PAIR_ID)], Some(2), Some(0), false).await.unwrap();
+        assert_eq!(next_price(&mut receiver).await, Some((2, 200)));
+        assert_eq!(next_price(&mut receiver).await, Some((3, 300)));
+        assert!(next_non_processed(&mut receiver).await.is_none());
     }
 
+    #[rstest]
+    #[tokio::test]
+    async fn receive_event_backfills_by_chunks() {
+        let source = ScriptedSource::new();
+        for _ in 0..MAX_BLOCKS_PER_REQUEST + 10 {
+            source.push_block(vec![]);
+        }
+
+        let mut receiver = receive_event(source.clone(), vec![String::from(PAIR_ID)], Some(1), Some(0), false).await.unwrap();
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ListenerEvent::Processed { block_number }) if block_number == MAX_BLOCKS_PER_REQUEST
+        ));
+        assert!(matches!(
+            receiver.recv().await,
+            Some(ListenerEvent::Processed { block_number }) if block_number == MAX_BLOCKS_PER_REQUEST + 10
+        ));
+    }
 
+    #[rstest]
     #[tokio::test]
+    async fn receive_event_follows_several_pairs() {
+        let source = ScriptedSource::new();
+        source.push_block(vec![
+            scripted_transaction(PAIR_ID, 10, 100),
+            scripted_transaction("ETH/USD", 11, 5),
+            scripted_transaction("WBTC/USD", 12, 7)
+        ]);
+
+        let pair_ids = vec![String::from(PAIR_ID), String::from("ETH/USD")];
+        let mut receiver = receive_event(source.clone(), pair_ids, None, Some(0), false).await.unwrap();
+        assert_eq!(next_price(&mut receiver).await, Some((1, 100)));
+        assert_eq!(next_price(&mut receiver).await, Some((1, 5)));
+        assert!(next_non_processed(&mut receiver).await.is_none());
+    }
+
     #[rstest]
-    #[case(true, "sdszaf")]
-    #[case(false, CONTRACT_ADDR)]
-    async fn receive_event_return_receiver_failed(
-        #[case] is_rpc_url_ok: bool,
-        #[case] contract_addr: &str,
-        rpc_url: String
-    ) {
-        assert!(receive_event(if is_rpc_url_ok {&rpc_url} else {"skjd"}, contract_addr, PAIR_ID, Some(1), true).await.is_none());
+    #[tokio::test]
+    async fn receive_event_follows_new_heads_then_falls_back_to_polling() {
+        let source = ScriptedSource::new();
+        source.push_block(vec![scripted_transaction(PAIR_ID, 10, 100)]);
+        let (heads, heads_receiver) = mpsc::channel(8);
+        source.subscribe_via(mock_websocket_server(MockWebSocket::Heads(heads_receiver)).await);
+
+        let mut receiver = receive_event(source.clone(), vec![String::from(PAIR_ID)], None, Some(3), false).await.unwrap();
+        assert_eq!(next_price(&mut receiver).await, Some((1, 100)));
+
+        // While subscribed, the block is read as soon as it is notified rather than at the next poll.
+        let block_number = source.push_block(vec![scripted_transaction(PAIR_ID, 20, 200)]);
+        heads.send(block_number).await.unwrap();
+        assert_eq!(timeout(POLL_INTERVAL / 2, next_price(&mut receiver)).await.ok(), Some(Some((2, 200))));
+
+        drop(heads);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 30, 300)]);
+        assert_eq!(timeout(Duration::from_secs(5), next_price(&mut receiver)).await.ok(), Some(Some((3, 300))));
     }
 }
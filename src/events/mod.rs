@@ -1,3 +1,18 @@
+pub(crate) mod dedup;
+pub(crate) mod entry_filter;
+pub(crate) mod evm_source;
+pub(crate) mod failover;
+pub(crate) mod finality;
 pub(crate) mod listener;
+pub(crate) mod oracle_event;
+pub(crate) mod quorum;
+pub(crate) mod recorder;
+pub(crate) mod reorg;
+pub(crate) mod replay;
+pub(crate) mod retry;
+pub(crate) mod source;
 pub(crate) mod spot_entry;
+pub(crate) mod starknet_source;
 pub(crate) mod transaction;
+pub(crate) mod uniswap_source;
+pub(crate) mod websocket;
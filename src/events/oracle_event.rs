@@ -0,0 +1,372 @@
+use std::fmt;
+
+use starknet::core::{types::Felt, utils::get_selector_from_name};
+
+use crate::events::spot_entry::{felt_to_u128, felt_to_u64, felt_to_utf8_str, SpotEntry};
+
+/// `starknet_keccak("SubmittedSpotEntry")`
+pub(crate) const SUBMITTED_SPOT_ENTRY: Felt =
+    Felt::from_hex_unchecked("0x280bb2099800026f90c334a3a23888ffe718a2920ffbbf4f44c6d3d5efb613c");
+/// `starknet_keccak("SubmittedFutureEntry")`
+pub(crate) const SUBMITTED_FUTURE_ENTRY: Felt =
+    Felt::from_hex_unchecked("0x3cba4bf3c7927a18934ab2c6b812a768d586eb481cef0b0ff1534b85613dadc");
+/// `starknet_keccak("CheckpointSpotEntry")`
+pub(crate) const CHECKPOINT_SPOT_ENTRY: Felt =
+    Felt::from_hex_unchecked("0x1feaa3d5cdbfc5df79bd2ade44c1ca611c1f647a372ee0c023a820597ff4927");
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FutureEntry {
+    pub(crate) timestamp: u64,
+    pub(crate) source: String,
+    pub(crate) publisher: String,
+    pub(crate) price: u128,
+    pub(crate) pair_id: String,
+    pub(crate) volume: u128,
+    pub(crate) expiration_timestamp: u64
+}
+
+/// An event emitted by the Pragma oracle contract, decoded from its keys and data.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum OracleEvent {
+    SpotEntry(SpotEntry),
+    FutureEntry(FutureEntry),
+    /// The oracle has aggregated the spot entries of `pair_id` into a checkpoint.
+    Checkpoint { pair_id: String }
+}
+
+impl fmt::Display for OracleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OracleEvent::SpotEntry(entry) => write!(f, "{entry}"),
+            OracleEvent::FutureEntry(entry) => write!(
+                f,
+                "FutureEntry {{ timestamp: {}, source: {}, publisher: {}, price: {}, pair_id: {}, volume: {}, expiration_timestamp: {} }}",
+                entry.timestamp,
+                entry.source,
+                entry.publisher,
+                entry.price,
+                entry.pair_id,
+                entry.volume,
+                entry.expiration_timestamp
+            ),
+            OracleEvent::Checkpoint { pair_id } => write!(f, "CheckpointSpotEntry {{ pair_id: {pair_id} }}")
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum DecodeError {
+    /// The event has no key, so its selector is unknown.
+    MissingSelector,
+    UnknownSelector(Felt),
+    TooShort { event: &'static str, expected: usize, actual: usize },
+    InvalidField { event: &'static str, field: &'static str, reason: String }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::MissingSelector => write!(f, "The event has no selector"),
+            DecodeError::UnknownSelector(selector) => write!(f, "Unknown event selector {selector:#x}"),
+            DecodeError::TooShort { event, expected, actual } => {
+                write!(f, "{event} expects {expected} data fields but got {actual}")
+            }
+            DecodeError::InvalidField { event, field, reason } => write!(f, "Invalid {event}.{field} : {reason}")
+        }
+    }
+}
+
+/// Reads the data fields of one event in order, so that each field is named in the errors.
+struct Fields<'a> {
+    event: &'static str,
+    data: &'a [Felt],
+    position: usize
+}
+
+impl<'a> Fields<'a> {
+    /// Fails unless `data` holds at least `expected` fields, so that reading them cannot go out of bounds.
+    fn new(event: &'static str, data: &'a [Felt], expected: usize) -> Result<Self, DecodeError> {
+        if data.len() < expected {
+            return Err(DecodeError::TooShort { event, expected, actual: data.len() });
+        }
+        Ok(Self { event, data, position: 0 })
+    }
+
+    fn next<T>(&mut self, field: &'static str, convert: fn(Felt) -> Result<T, String>) -> Result<T, DecodeError> {
+        let felt = self.data[self.position];
+        self.position += 1;
+        convert(felt).map_err(|reason| DecodeError::InvalidField { event: self.event, field, reason })
+    }
+}
+
+/// Decodes the data of a `SubmittedSpotEntry`, or of an event laid out the same way but whose pair id is the key
+/// `pair_id` rather than a data field.
+fn decode_spot_entry(data: &[Felt], pair_id: Option<Felt>) -> Result<SpotEntry, DecodeError> {
+    let event = "SubmittedSpotEntry";
+    let mut fields = Fields::new(event, data, if pair_id.is_some() { 5 } else { 6 })?;
+    let timestamp = fields.next("timestamp", felt_to_u64)?;
+    let source = fields.next("source", felt_to_utf8_str)?;
+    let publisher = fields.next("publisher", felt_to_utf8_str)?;
+    let price = fields.next("price", felt_to_u128)?;
+    let pair_id = match pair_id {
+        Some(key) => {
+            felt_to_utf8_str(key).map_err(|reason| DecodeError::InvalidField { event, field: "pair_id", reason })?
+        }
+        None => fields.next("pair_id", felt_to_utf8_str)?
+    };
+    Ok(SpotEntry { timestamp, source, publisher, price, pair_id, volume: fields.next("volume", felt_to_u128)? })
+}
+
+/// The events of the oracle contract read as spot entries: the ones of `selectors`, all laid out as
+/// `SubmittedSpotEntry`, their pair id being the key at `pair_id_key` when the contract indexes it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SpotEntryLayout {
+    selectors: Vec<Felt>,
+    pair_id_key: Option<usize>
+}
+
+impl Default for SpotEntryLayout {
+    fn default() -> Self {
+        Self { selectors: vec![SUBMITTED_SPOT_ENTRY], pair_id_key: None }
+    }
+}
+
+impl SpotEntryLayout {
+    pub(crate) fn new(selectors: Vec<Felt>, pair_id_key: Option<usize>) -> Result<Self, String> {
+        if selectors.is_empty() {
+            return Err(String::from("At least one event selector is expected"));
+        }
+        if pair_id_key == Some(0) {
+            return Err(String::from("The key 0 is the selector of the event, not its pair id"));
+        }
+        Ok(Self { selectors, pair_id_key })
+    }
+
+    /// Keys of an `EventFilter` matching the events of `selectors`, and only the ones of `pair_ids` when the pair id is
+    /// a key and `pair_ids` is not empty.
+    pub(crate) fn filter_keys(&self, pair_ids: &[Felt]) -> Vec<Vec<Felt>> {
+        let mut keys = vec![self.selectors.clone()];
+        if let Some(position) = self.pair_id_key.filter(|_| !pair_ids.is_empty()) {
+            keys.resize(position, vec![]);
+            keys.push(pair_ids.to_vec());
+        }
+        keys
+    }
+
+    pub(crate) fn decode(&self, keys: &[Felt], data: &[Felt]) -> Result<SpotEntry, DecodeError> {
+        let selector = *keys.first().ok_or(DecodeError::MissingSelector)?;
+        if !self.selectors.contains(&selector) {
+            return Err(DecodeError::UnknownSelector(selector));
+        }
+        let pair_id = match self.pair_id_key {
+            Some(position) => Some(*keys.get(position).ok_or(DecodeError::InvalidField {
+                event: "SubmittedSpotEntry",
+                field: "pair_id",
+                reason: format!("The event has no key {position}")
+            })?),
+            None => None
+        };
+        decode_spot_entry(data, pair_id)
+    }
+}
+
+/// Either the name of an event, e.g. `SubmittedSpotEntry`, or its selector in hexadecimal.
+pub(crate) fn parse_selector(value: &str) -> Result<Felt, String> {
+    if value.starts_with("0x") {
+        Felt::from_hex(value).map_err(|e| format!("Invalid selector {value} : {e}"))
+    } else {
+        get_selector_from_name(value).map_err(|e| format!("Invalid event name {value} : {e}"))
+    }
+}
+
+/// Decodes an event of the oracle contract, recognised by its selector: the first of its `keys`.
+pub(crate) fn decode_event(keys: &[Felt], data: &[Felt]) -> Result<OracleEvent, DecodeError> {
+    let selector = *keys.first().ok_or(DecodeError::MissingSelector)?;
+
+    if selector == SUBMITTED_SPOT_ENTRY {
+        decode_spot_entry(data, None).map(OracleEvent::SpotEntry)
+    } else if selector == SUBMITTED_FUTURE_ENTRY {
+        let mut fields = Fields::new("SubmittedFutureEntry", data, 7)?;
+        Ok(OracleEvent::FutureEntry(FutureEntry {
+            timestamp: fields.next("timestamp", felt_to_u64)?,
+            source: fields.next("source", felt_to_utf8_str)?,
+            publisher: fields.next("publisher", felt_to_utf8_str)?,
+            price: fields.next("price", felt_to_u128)?,
+            pair_id: fields.next("pair_id", felt_to_utf8_str)?,
+            volume: fields.next("volume", felt_to_u128)?,
+            expiration_timestamp: fields.next("expiration_timestamp", felt_to_u64)?
+        }))
+    } else if selector == CHECKPOINT_SPOT_ENTRY {
+        let mut fields = Fields::new("CheckpointSpotEntry", data, 1)?;
+        Ok(OracleEvent::Checkpoint { pair_id: fields.next("pair_id", felt_to_utf8_str)? })
+    } else {
+        Err(DecodeError::UnknownSelector(selector))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use starknet::core::{types::Felt, utils::{cairo_short_string_to_felt, get_selector_from_name}};
+
+    use super::{
+        decode_event,
+        parse_selector,
+        DecodeError,
+        FutureEntry,
+        OracleEvent,
+        SpotEntryLayout,
+        CHECKPOINT_SPOT_ENTRY,
+        SUBMITTED_FUTURE_ENTRY,
+        SUBMITTED_SPOT_ENTRY
+    };
+    use crate::events::spot_entry::SpotEntry;
+
+    fn short_string(value: &str) -> Felt {
+        cairo_short_string_to_felt(value).unwrap()
+    }
+
+    fn entry_data() -> Vec<Felt> {
+        vec![
+            Felt::from(1_700_000_000u64),
+            short_string("BINANCE"),
+            short_string("PRAGMA"),
+            Felt::from(6_500_000_000_000u128),
+            short_string("BTC/USD"),
+            Felt::from(3u8)
+        ]
+    }
+
+    #[rstest]
+    #[case(SUBMITTED_SPOT_ENTRY, "SubmittedSpotEntry")]
+    #[case(SUBMITTED_FUTURE_ENTRY, "SubmittedFutureEntry")]
+    #[case(CHECKPOINT_SPOT_ENTRY, "CheckpointSpotEntry")]
+    fn selectors_match_event_names(#[case] selector: Felt, #[case] name: &str) {
+        assert_eq!(selector, get_selector_from_name(name).unwrap());
+    }
+
+    #[rstest]
+    fn decode_each_oracle_event() {
+        let spot_entry = SpotEntry {
+            timestamp: 1_700_000_000,
+            source: String::from("BINANCE"),
+            publisher: String::from("PRAGMA"),
+            price: 6_500_000_000_000,
+            pair_id: String::from("BTC/USD"),
+            volume: 3
+        };
+        assert_eq!(
+            decode_event(&[SUBMITTED_SPOT_ENTRY], &entry_data()),
+            Ok(OracleEvent::SpotEntry(spot_entry))
+        );
+
+        let mut future_data = entry_data();
+        future_data.push(Felt::from(1_800_000_000u64));
+        assert_eq!(
+            decode_event(&[SUBMITTED_FUTURE_ENTRY], &future_data),
+            Ok(OracleEvent::FutureEntry(FutureEntry {
+                timestamp: 1_700_000_000,
+                source: String::from("BINANCE"),
+                publisher: String::from("PRAGMA"),
+                price: 6_500_000_000_000,
+                pair_id: String::from("BTC/USD"),
+                volume: 3,
+                expiration_timestamp: 1_800_000_000
+            }))
+        );
+
+        assert_eq!(
+            decode_event(&[CHECKPOINT_SPOT_ENTRY], &[short_string("ETH/USD")]),
+            Ok(OracleEvent::Checkpoint { pair_id: String::from("ETH/USD") })
+        );
+    }
+
+    #[rstest]
+    #[case(vec![], entry_data(), DecodeError::MissingSelector)]
+    #[case(vec![Felt::ONE], entry_data(), DecodeError::UnknownSelector(Felt::ONE))]
+    #[case(
+        vec![SUBMITTED_SPOT_ENTRY],
+        vec![Felt::ONE; 3],
+        DecodeError::TooShort { event: "SubmittedSpotEntry", expected: 6, actual: 3 }
+    )]
+    #[case(
+        vec![SUBMITTED_FUTURE_ENTRY],
+        entry_data(),
+        DecodeError::TooShort { event: "SubmittedFutureEntry", expected: 7, actual: 6 }
+    )]
+    #[case(
+        vec![CHECKPOINT_SPOT_ENTRY],
+        vec![],
+        DecodeError::TooShort { event: "CheckpointSpotEntry", expected: 1, actual: 0 }
+    )]
+    fn decode_rejects_malformed_event(#[case] keys: Vec<Felt>, #[case] data: Vec<Felt>, #[case] expected: DecodeError) {
+        assert_eq!(decode_event(&keys, &data), Err(expected));
+    }
+
+    #[rstest]
+    fn decode_names_invalid_field() {
+        let mut data = entry_data();
+        data[0] = Felt::MAX;
+        assert!(matches!(
+            decode_event(&[SUBMITTED_SPOT_ENTRY], &data),
+            Err(DecodeError::InvalidField { event: "SubmittedSpotEntry", field: "timestamp", .. })
+        ));
+    }
+
+    #[rstest]
+    #[case("SubmittedSpotEntry", Ok(SUBMITTED_SPOT_ENTRY))]
+    #[case("0x1", Ok(Felt::ONE))]
+    #[case("0xz", Err(String::from("Invalid selector 0xz : Failed to create Felt from string")))]
+    fn parse_selector_from_name_or_hex(#[case] value: &str, #[case] expected: Result<Felt, String>) {
+        assert_eq!(parse_selector(value), expected);
+    }
+
+    #[rstest]
+    #[case(vec![], None, Err(String::from("At least one event selector is expected")))]
+    #[case(vec![Felt::ONE], Some(0), Err(String::from("The key 0 is the selector of the event, not its pair id")))]
+    #[case(vec![Felt::ONE], Some(2), Ok(()))]
+    fn spot_entry_layout_validates_keys(
+        #[case] selectors: Vec<Felt>,
+        #[case] pair_id_key: Option<usize>,
+        #[case] expected: Result<(), String>
+    ) {
+        assert_eq!(SpotEntryLayout::new(selectors, pair_id_key).map(|_| ()), expected);
+    }
+
+    #[rstest]
+    #[case(None, vec![short_string("BTC/USD")], vec![vec![Felt::ONE, Felt::TWO]])]
+    #[case(Some(2), vec![], vec![vec![Felt::ONE, Felt::TWO]])]
+    #[case(Some(1), vec![short_string("BTC/USD")], vec![vec![Felt::ONE, Felt::TWO], vec![short_string("BTC/USD")]])]
+    #[case(
+        Some(3),
+        vec![short_string("BTC/USD")],
+        vec![vec![Felt::ONE, Felt::TWO], vec![], vec![], vec![short_string("BTC/USD")]]
+    )]
+    fn spot_entry_layout_filters_pair_ids_in_keys(
+        #[case] pair_id_key: Option<usize>,
+        #[case] pair_ids: Vec<Felt>,
+        #[case] expected: Vec<Vec<Felt>>
+    ) {
+        let layout = SpotEntryLayout::new(vec![Felt::ONE, Felt::TWO], pair_id_key).unwrap();
+        assert_eq!(layout.filter_keys(&pair_ids), expected);
+    }
+
+    #[rstest]
+    fn spot_entry_layout_reads_pair_id_from_keys() {
+        let layout = SpotEntryLayout::new(vec![Felt::ONE], Some(1)).unwrap();
+        let mut data = entry_data();
+        data.remove(4);
+
+        let entry = layout.decode(&[Felt::ONE, short_string("ETH/USD")], &data).unwrap();
+        assert_eq!((entry.pair_id.as_str(), entry.volume), ("ETH/USD", 3));
+        assert_eq!(
+            layout.decode(&[SUBMITTED_SPOT_ENTRY], &entry_data()),
+            Err(DecodeError::UnknownSelector(SUBMITTED_SPOT_ENTRY))
+        );
+        assert!(matches!(
+            layout.decode(&[Felt::ONE], &data),
+            Err(DecodeError::InvalidField { field: "pair_id", .. })
+        ));
+    }
+}
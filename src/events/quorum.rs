@@ -0,0 +1,227 @@
+use std::{collections::{HashMap, HashSet}, sync::Mutex};
+
+use futures_util::future::join_all;
+use tokio::sync::mpsc::Receiver;
+
+use crate::events::{failover::ProviderStatus, source::{BlockHeader, EventSource}, transaction::Transaction};
+
+/// Asks every provider and only trusts what at least `quorum` of them agree on, so that a single compromised or
+/// buggy provider cannot inject prices.
+pub(crate) struct QuorumSource<S> {
+    providers: Vec<S>,
+    statuses: Mutex<Vec<ProviderStatus>>,
+    quorum: usize
+}
+
+impl<S: EventSource> QuorumSource<S> {
+    pub(crate) fn new(providers: Vec<(String, S)>, quorum: usize) -> Result<Self, String> {
+        if quorum == 0 || quorum > providers.len() {
+            return Err(format!("The quorum must be between 1 and {} providers, got {quorum}", providers.len()));
+        }
+        let (names, providers): (Vec<String>, Vec<S>) = providers.into_iter().unzip();
+        let statuses = names.into_iter().map(|name| ProviderStatus::new(name, true)).collect();
+        Ok(Self { providers, statuses: Mutex::new(statuses), quorum })
+    }
+
+    /// Keeps the successful answers, failing when fewer than `quorum` providers answered.
+    fn answers<T>(&self, what: &str, results: Vec<Result<T, String>>) -> Result<Vec<T>, String> {
+        let mut statuses = self.statuses.lock().unwrap();
+        for (status, result) in statuses.iter_mut().zip(&results) {
+            status.record(result);
+        }
+        let answers: Vec<T> = results.into_iter().filter_map(Result::ok).collect();
+        if answers.len() < self.quorum {
+            return Err(format!("Only {}/{} providers answered {what}", answers.len(), self.quorum));
+        }
+        Ok(answers)
+    }
+
+    /// The transactions reported by at least `quorum` providers, in the order of the first provider reporting them.
+    /// A transaction reported several times by the same provider is counted once.
+    fn agreed(&self, answers: Vec<Vec<Transaction>>) -> Vec<Transaction> {
+        let mut votes: HashMap<&Transaction, usize> = HashMap::new();
+        let mut ordered: Vec<&Transaction> = vec![];
+        for transactions in &answers {
+            let mut seen = HashSet::new();
+            for transaction in transactions.iter().filter(|transaction| seen.insert(*transaction)) {
+                let count = votes.entry(transaction).or_default();
+                if *count == 0 {
+                    ordered.push(transaction);
+                }
+                *count += 1;
+            }
+        }
+
+        ordered
+            .into_iter()
+            .filter(|transaction| {
+                let count = votes[transaction];
+                if count < self.quorum {
+                    eprintln!(
+                        "⚠️ [block:{}] Dropping {} reported by only {count}/{} providers",
+                        transaction.block_number, transaction.transaction_hash, self.quorum
+                    );
+                }
+                count >= self.quorum
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl<S: EventSource> EventSource for QuorumSource<S> {
+    /// The highest block reached by at least `quorum` providers.
+    async fn block_number(&self) -> Result<u64, String> {
+        let results = join_all(self.providers.iter().map(|provider| provider.block_number())).await;
+        {
+            let mut statuses = self.statuses.lock().unwrap();
+            for (status, result) in statuses.iter_mut().zip(&results) {
+                if let Ok(block_number) = result {
+                    status.block_number = Some(*block_number);
+                }
+            }
+        }
+        let mut block_numbers = self.answers("block_number", results)?;
+
+        block_numbers.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(block_numbers[self.quorum - 1])
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        let results = join_all(self.providers.iter().map(|provider| provider.block_header(block_number))).await;
+        let headers = self.answers("block_header", results)?;
+
+        let mut votes: HashMap<&BlockHeader, usize> = HashMap::new();
+        for header in &headers {
+            *votes.entry(header).or_default() += 1;
+        }
+        votes
+            .into_iter()
+            .find(|(_, count)| *count >= self.quorum)
+            .map(|(header, _)| header.clone())
+            .ok_or(format!("The providers disagree on the block n°{block_number}"))
+    }
+
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        let results =
+            join_all(self.providers.iter().map(|provider| provider.get_transactions(from_block, to_block))).await;
+        Ok(self.agreed(self.answers("get_transactions", results)?))
+    }
+
+    async fn get_pending_transactions(&self, block_number: u64) -> Result<Vec<Transaction>, String> {
+        let results =
+            join_all(self.providers.iter().map(|provider| provider.get_pending_transactions(block_number))).await;
+        Ok(self.agreed(self.answers("get_pending_transactions", results)?))
+    }
+
+    /// The highest block that at least `quorum` providers consider final.
+    async fn finalized_block_number(&self, latest_block: u64) -> Result<u64, String> {
+        let results =
+            join_all(self.providers.iter().map(|provider| provider.finalized_block_number(latest_block))).await;
+        let mut block_numbers = self.answers("finalized_block_number", results)?;
+        block_numbers.sort_unstable_by(|a, b| b.cmp(a));
+        Ok(block_numbers[self.quorum - 1])
+    }
+
+    fn providers(&self) -> Vec<ProviderStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    /// The new blocks of a single provider are only used to wake the listener up, the events are still verified.
+    async fn subscribe_new_heads(&self) -> Result<Receiver<u64>, String> {
+        let mut last_error = String::from("No RPC provider");
+        for provider in &self.providers {
+            match provider.subscribe_new_heads().await {
+                Ok(receiver) => return Ok(receiver),
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::QuorumSource;
+    use crate::events::source::{scripted_transaction, EventSource, ScriptedSource};
+
+    const PAIR_ID: &str = "BTC/USD";
+
+    fn quorum_of(sources: &[ScriptedSource], quorum: usize) -> QuorumSource<ScriptedSource> {
+        let providers = sources
+            .iter()
+            .enumerate()
+            .map(|(index, source)| (format!("#{index}"), source.clone()))
+            .collect();
+        QuorumSource::new(providers, quorum).unwrap()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn get_transactions_drops_events_without_quorum() {
+        let sources = [ScriptedSource::new(), ScriptedSource::new(), ScriptedSource::new()];
+        for source in &sources[..2] {
+            source.push_block(vec![scripted_transaction(PAIR_ID, 10, 100)]);
+        }
+        sources[2].push_block(vec![scripted_transaction(PAIR_ID, 10, 999_999)]);
+
+        let transactions = quorum_of(&sources, 2).get_transactions(0, 1).await.unwrap();
+        assert_eq!(transactions.iter().map(|t| t.spot_entry.price).collect::<Vec<_>>(), vec![100]);
+
+        let transactions = quorum_of(&sources, 3).get_transactions(0, 1).await.unwrap();
+        assert!(transactions.is_empty());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn block_number_reached_by_quorum() {
+        let sources = [ScriptedSource::new(), ScriptedSource::new(), ScriptedSource::new()];
+        for (source, height) in sources.iter().zip([5, 7, 9]) {
+            for _ in 0..height {
+                source.push_block(vec![]);
+            }
+        }
+
+        assert_eq!(quorum_of(&sources, 1).block_number().await, Ok(9));
+        assert_eq!(quorum_of(&sources, 2).block_number().await, Ok(7));
+
+        sources[1].fail_next_calls(1);
+        sources[2].fail_next_calls(1);
+        assert!(quorum_of(&sources, 2).block_number().await.is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn block_number_reports_the_height_of_each_provider() {
+        let sources = [ScriptedSource::new(), ScriptedSource::new(), ScriptedSource::new()];
+        for (source, height) in sources.iter().zip([5, 7, 9]) {
+            for _ in 0..height {
+                source.push_block(vec![]);
+            }
+        }
+        let quorum = quorum_of(&sources, 2);
+        quorum.block_number().await.unwrap();
+
+        sources[1].fail_next_calls(1);
+        sources[2].push_block(vec![]);
+        assert_eq!(quorum.block_number().await, Ok(5));
+        let providers: Vec<(String, Option<u64>, u32)> = quorum
+            .providers()
+            .into_iter()
+            .map(|status| (status.name, status.block_number, status.consecutive_failures))
+            .collect();
+        assert_eq!(providers, vec![
+            (String::from("#0"), Some(5), 0),
+            (String::from("#1"), Some(7), 1),
+            (String::from("#2"), Some(10), 0)
+        ]);
+    }
+
+    #[rstest]
+    fn quorum_must_be_reachable() {
+        assert!(QuorumSource::new(vec![(String::from("#0"), ScriptedSource::new())], 2).is_err());
+    }
+}
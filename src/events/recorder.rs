@@ -0,0 +1,217 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::{Mutex, PoisonError}
+};
+
+use serde::{Deserialize, Serialize};
+use starknet::core::types::{EmittedEvent, Felt};
+
+/// An emitted event as received from the RPC, before any decoding. Felts are hexadecimal strings.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct RawEvent {
+    pub(crate) block_number: u64,
+    pub(crate) block_hash: Option<String>,
+    pub(crate) transaction_hash: String,
+    pub(crate) from_address: String,
+    pub(crate) keys: Vec<String>,
+    pub(crate) data: Vec<String>
+}
+
+impl RawEvent {
+    /// Returns `None` for the events of the pending block, which have no number yet.
+    pub(crate) fn from_emitted(event: &EmittedEvent) -> Option<Self> {
+        Some(Self {
+            block_number: event.block_number?,
+            block_hash: event.block_hash.map(|hash| hash.to_fixed_hex_string()),
+            transaction_hash: event.transaction_hash.to_fixed_hex_string(),
+            from_address: event.from_address.to_fixed_hex_string(),
+            keys: event.keys.iter().map(Felt::to_hex_string).collect(),
+            data: event.data.iter().map(Felt::to_hex_string).collect()
+        })
+    }
+
+    pub(crate) fn to_emitted(&self) -> Result<EmittedEvent, String> {
+        let felt = |hex: &str| Felt::from_hex(hex).map_err(|e| format!("Invalid felt {hex} : {e}"));
+        Ok(EmittedEvent {
+            from_address: felt(&self.from_address)?,
+            keys: self.keys.iter().map(|key| felt(key)).collect::<Result<_, _>>()?,
+            data: self.data.iter().map(|data| felt(data)).collect::<Result<_, _>>()?,
+            block_hash: self.block_hash.as_deref().map(felt).transpose()?,
+            block_number: Some(self.block_number),
+            transaction_hash: felt(&self.transaction_hash)?
+        })
+    }
+}
+
+/// Number of the last recorded events remembered to skip the ones fetched again.
+const RECORDED_WINDOW: usize = 100_000;
+
+/// Remembers the last recorded events, each with its occurrence among the identical events of its transaction, so
+/// that an event fetched by several providers, or again by a retry, is recorded once.
+struct RecordedEvents {
+    recorded: VecDeque<(RawEvent, usize)>,
+    keys: HashSet<(RawEvent, usize)>
+}
+
+impl RecordedEvents {
+    fn new() -> Self {
+        Self { recorded: VecDeque::new(), keys: HashSet::new() }
+    }
+
+    /// Returns the events of `events` not recorded yet, remembering them.
+    /// The events of a transaction being fetched together, identical ones are told apart by their occurrence.
+    fn insert(&mut self, events: Vec<RawEvent>) -> Vec<RawEvent> {
+        let mut occurrences: HashMap<RawEvent, usize> = HashMap::new();
+        events
+            .into_iter()
+            .filter(|event| {
+                let occurrence = occurrences.entry(event.clone()).or_default();
+                let key = (event.clone(), *occurrence);
+                *occurrence += 1;
+                if !self.keys.insert(key.clone()) {
+                    return false;
+                }
+                if self.recorded.len() == RECORDED_WINDOW {
+                    if let Some(oldest) = self.recorded.pop_front() {
+                        self.keys.remove(&oldest);
+                    }
+                }
+                self.recorded.push_back(key);
+                true
+            })
+            .collect()
+    }
+}
+
+/// Appends every fetched event to a JSON Lines file, so that a run can be replayed offline.
+/// The recorder being shared by the providers, the events already recorded are skipped.
+pub(crate) struct EventRecorder {
+    file: Mutex<File>,
+    recorded: Mutex<RecordedEvents>
+}
+
+impl EventRecorder {
+    /// Opens `path` in append mode, keeping the events recorded by the previous runs.
+    pub(crate) fn create(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+        let mut recorded = RecordedEvents::new();
+        match load_recorded_events(path) {
+            Ok(events) => {
+                recorded.insert(events);
+            }
+            Err(e) => eprintln!("⚠️ Failed to read the recorded events, they may be recorded again : {e}")
+        }
+        Ok(Self { file: Mutex::new(file), recorded: Mutex::new(recorded) })
+    }
+
+    /// A failure to record is logged but does not stop the ingestion.
+    pub(crate) fn record(&self, events: &[EmittedEvent]) {
+        let mut recorded = self.recorded.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut lines = String::new();
+        for event in recorded.insert(events.iter().filter_map(RawEvent::from_emitted).collect()) {
+            match serde_json::to_string(&event) {
+                Ok(line) => {
+                    lines.push_str(&line);
+                    lines.push('\n');
+                }
+                Err(e) => eprintln!("⚠️ Failed to record the event of {} : {e}", event.transaction_hash)
+            }
+        }
+        if let Err(e) = self.file.lock().unwrap_or_else(PoisonError::into_inner).write_all(lines.as_bytes()) {
+            eprintln!("⚠️ Failed to record {} events : {e}", events.len());
+        }
+    }
+}
+
+/// Reads the events recorded by `EventRecorder`, in the order they were recorded.
+pub(crate) fn load_recorded_events(path: &Path) -> Result<Vec<RawEvent>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(index, line)| {
+            let line = line.map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+            serde_json::from_str(&line).map_err(|e| format!("Invalid event at {}:{} : {e}", path.display(), index + 1))
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use starknet::core::types::{EmittedEvent, Felt};
+
+    use super::{load_recorded_events, EventRecorder, RawEvent};
+
+    fn recorder_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("twaplast-recorder-{name}-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn event(transaction_hash: u8, block_hash: u8) -> EmittedEvent {
+        EmittedEvent {
+            from_address: Felt::from(7u8),
+            keys: vec![Felt::from(1u8)],
+            data: vec![Felt::from(2u8), Felt::from(3u8)],
+            block_hash: Some(Felt::from(block_hash)),
+            block_number: Some(12),
+            transaction_hash: Felt::from(transaction_hash)
+        }
+    }
+
+    #[rstest]
+    fn record_then_load() {
+        let path = recorder_path("load");
+        let event = event(5, 9);
+        let pending_event = EmittedEvent { block_hash: None, block_number: None, ..event.clone() };
+
+        EventRecorder::create(&path).unwrap().record(&[event.clone(), pending_event]);
+
+        let recorded = load_recorded_events(&path).unwrap();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].to_emitted(), Ok(event.clone()));
+        assert_eq!(Some(recorded[0].clone()), RawEvent::from_emitted(&event));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[rstest]
+    fn record_skips_the_events_already_recorded() {
+        let path = recorder_path("once");
+        let recorder = EventRecorder::create(&path).unwrap();
+        // Two identical events of a transaction, fetched by two providers.
+        recorder.record(&[event(5, 9), event(5, 9), event(6, 9)]);
+        recorder.record(&[event(5, 9), event(5, 9), event(6, 9)]);
+        // A retry reading more events, and the same transaction in a reorganized block.
+        recorder.record(&[event(5, 9), event(5, 9), event(5, 9), event(5, 10)]);
+        // A restart.
+        EventRecorder::create(&path).unwrap().record(&[event(5, 9), event(6, 9), event(7, 9)]);
+
+        let recorded: Vec<_> = load_recorded_events(&path)
+            .unwrap()
+            .iter()
+            .map(|event| (event.transaction_hash.clone(), event.block_hash.clone()))
+            .collect();
+        let hash = |value: u8| Felt::from(value).to_fixed_hex_string();
+        assert_eq!(recorded, vec![
+            (hash(5), Some(hash(9))),
+            (hash(5), Some(hash(9))),
+            (hash(6), Some(hash(9))),
+            (hash(5), Some(hash(9))),
+            (hash(5), Some(hash(10))),
+            (hash(7), Some(hash(9)))
+        ]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use crate::events::source::{BlockHeader, EventSource};
+
+/// Remembers the hashes of the last processed blocks to detect chain reorganisations.
+pub(crate) struct ChainTracker {
+    headers: VecDeque<BlockHeader>,
+    max_depth: usize
+}
+
+impl ChainTracker {
+    pub(crate) fn new(max_depth: usize) -> Self {
+        Self {
+            headers: VecDeque::with_capacity(max_depth),
+            max_depth
+        }
+    }
+
+    pub(crate) fn last(&self) -> Option<&BlockHeader> {
+        self.headers.back()
+    }
+
+    pub(crate) fn hash_of(&self, block_number: u64) -> Option<&str> {
+        let first = self.headers.front()?.block_number;
+        self.headers
+            .get(block_number.checked_sub(first)? as usize)
+            .map(|header| header.block_hash.as_str())
+    }
+
+    /// Appends the header of the block following the last tracked one.
+    /// A header that does not follow the last tracked block restarts the tracking from it.
+    pub(crate) fn push(&mut self, header: BlockHeader) {
+        if self.last().is_some_and(|last| last.block_number + 1 != header.block_number) {
+            self.headers.clear();
+        }
+        if self.headers.len() == self.max_depth {
+            self.headers.pop_front();
+        }
+        self.headers.push_back(header);
+    }
+
+    /// Forgets every block after `fork_block`.
+    pub(crate) fn rollback(&mut self, fork_block: u64) {
+        while self.last().is_some_and(|last| last.block_number > fork_block) {
+            self.headers.pop_back();
+        }
+    }
+
+    /// Fetches the headers from the last tracked block (or `from_block` when nothing is tracked) up to `to_block`.
+    /// Returns the fork block when a header does not link to the tracked chain; the tracker is then rolled back to
+    /// it and must be filled again.
+    pub(crate) async fn follow<S: EventSource>(
+        &mut self,
+        source: &S,
+        from_block: u64,
+        to_block: u64
+    ) -> Result<Option<u64>, String> {
+        // Blocks older than the tracked depth could not be rolled back anyway.
+        let start = self
+            .last()
+            .map_or(from_block, |last| last.block_number + 1)
+            .max((to_block + 1).saturating_sub(self.max_depth as u64));
+
+        for block_number in start..=to_block {
+            let header = source.block_header(block_number).await?;
+            let expected_parent = block_number.checked_sub(1).and_then(|parent| self.hash_of(parent));
+
+            if expected_parent.is_some_and(|parent_hash| parent_hash != header.parent_hash) {
+                let fork_block = self.find_fork(source, block_number - 1).await?;
+                self.rollback(fork_block);
+                return Ok(Some(fork_block));
+            }
+            self.push(header);
+        }
+        Ok(None)
+    }
+
+    /// Walks back from `block_number` to the last block that is still part of the canonical chain.
+    async fn find_fork<S: EventSource>(&self, source: &S, block_number: u64) -> Result<u64, String> {
+        let Some(first) = self.headers.front().map(|header| header.block_number) else {
+            return Ok(block_number);
+        };
+
+        for candidate in (first..=block_number).rev() {
+            let header = source.block_header(candidate).await?;
+            if self.hash_of(candidate) == Some(header.block_hash.as_str()) {
+                return Ok(candidate);
+            }
+        }
+
+        eprintln!("⚠️ Reorganisation deeper than the {} tracked blocks", self.max_depth);
+        Ok(first.saturating_sub(1))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::ChainTracker;
+    use crate::events::source::{BlockHeader, ScriptedSource};
+
+    fn header(block_number: u64, block_hash: &str, parent_hash: &str) -> BlockHeader {
+        BlockHeader {
+            block_number,
+            block_hash: String::from(block_hash),
+            parent_hash: String::from(parent_hash),
+            timestamp: block_number
+        }
+    }
+
+    #[rstest]
+    fn tracker_keeps_max_depth_blocks() {
+        let mut tracker = ChainTracker::new(2);
+        tracker.push(header(1, "0x1", "0x0"));
+        tracker.push(header(2, "0x2", "0x1"));
+        tracker.push(header(3, "0x3", "0x2"));
+
+        assert_eq!(tracker.hash_of(1), None);
+        assert_eq!(tracker.hash_of(2), Some("0x2"));
+        assert_eq!(tracker.hash_of(3), Some("0x3"));
+
+        tracker.rollback(2);
+        assert_eq!(tracker.last().map(|header| header.block_number), Some(2));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn follow_detects_fork_block() {
+        let source = ScriptedSource::new();
+        for _ in 0..5 {
+            source.push_block(vec![]);
+        }
+
+        let mut tracker = ChainTracker::new(64);
+        assert_eq!(tracker.follow(&source, 0, 5).await, Ok(None));
+
+        source.reorg(3);
+        source.push_block(vec![]);
+        source.push_block(vec![]);
+        source.push_block(vec![]);
+
+        assert_eq!(tracker.follow(&source, 0, 6).await, Ok(Some(3)));
+        assert_eq!(tracker.last().map(|header| header.block_number), Some(3));
+        assert_eq!(tracker.follow(&source, 0, 6).await, Ok(None));
+        assert_eq!(tracker.last().map(|header| header.block_number), Some(6));
+    }
+}
@@ -0,0 +1,226 @@
+use std::{collections::BTreeMap, path::Path, time::Instant};
+
+use starknet::core::types::EmittedEvent;
+
+use crate::events::{
+    oracle_event::SpotEntryLayout,
+    recorder::load_recorded_events,
+    source::{BlockHeader, EventSource},
+    starknet_source::events_to_transactions,
+    transaction::Transaction
+};
+
+struct ReplayBlock {
+    hash: String,
+    /// Latest spot entry timestamp of the block, or of a previous one when it has none.
+    timestamp: u64,
+    events: Vec<EmittedEvent>
+}
+
+/// Feeds the events recorded by `EventRecorder` back to the listener, releasing the blocks as time goes by.
+/// The recorded blocks are replayed `speed` times faster than they were produced, or all at once when `speed` is 0.
+pub(crate) struct ReplaySource {
+    blocks: BTreeMap<u64, ReplayBlock>,
+    layout: SpotEntryLayout,
+    speed: f64,
+    started_at: Instant
+}
+
+impl ReplaySource {
+    /// A block recorded several times keeps the events of its last recorded hash, the other ones having been orphaned
+    /// by a reorganisation. The recorder skipping the events read again, identical events of a transaction are all
+    /// replayed, as they were received.
+    /// The events are decoded with `layout`, the one of the contract they were recorded from.
+    pub(crate) fn load(path: &Path, speed: f64, layout: SpotEntryLayout) -> Result<Self, String> {
+        let mut blocks: BTreeMap<u64, ReplayBlock> = BTreeMap::new();
+        for raw_event in load_recorded_events(path)? {
+            let hash = raw_event.block_hash.clone().unwrap_or(format!("0x{:x}", raw_event.block_number));
+            let event = raw_event.to_emitted()?;
+            let block = blocks
+                .entry(raw_event.block_number)
+                .or_insert_with(|| ReplayBlock { hash: hash.clone(), timestamp: 0, events: vec![] });
+            if block.hash != hash {
+                block.hash = hash;
+                block.events.clear();
+            }
+            block.events.push(event);
+        }
+        if blocks.is_empty() {
+            return Err(format!("No event recorded in {}", path.display()));
+        }
+
+        let entry_timestamps = |block: &ReplayBlock| {
+            block
+                .events
+                .iter()
+                .filter_map(|event| layout.decode(&event.keys, &event.data).ok().map(|entry| entry.timestamp))
+                .max()
+        };
+        let mut timestamp = blocks.values().find_map(entry_timestamps).unwrap_or(0);
+        for block in blocks.values_mut() {
+            timestamp = entry_timestamps(block).unwrap_or(timestamp).max(timestamp);
+            block.timestamp = timestamp;
+        }
+
+        println!("📼 Replaying {} blocks from {}", blocks.len(), path.display());
+        Ok(Self { blocks, layout, speed, started_at: Instant::now() })
+    }
+
+    /// The last block whose time has come.
+    fn released_block(&self) -> u64 {
+        let (&first_block, first) = self.blocks.first_key_value().unwrap();
+        if self.speed <= 0.0 {
+            return *self.blocks.last_key_value().unwrap().0;
+        }
+        let replayed_seconds = self.started_at.elapsed().as_secs_f64() * self.speed;
+        self.blocks
+            .iter()
+            .take_while(|(_, block)| (block.timestamp - first.timestamp) as f64 <= replayed_seconds)
+            .last()
+            .map_or(first_block, |(&block_number, _)| block_number)
+    }
+
+    /// The blocks without recorded events get a hash derived from their number.
+    fn hash_of(&self, block_number: u64) -> String {
+        self.blocks
+            .get(&block_number)
+            .map_or(format!("0x{block_number:x}"), |block| block.hash.clone())
+    }
+}
+
+impl EventSource for ReplaySource {
+    async fn block_number(&self) -> Result<u64, String> {
+        Ok(self.released_block())
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        if block_number > self.released_block() {
+            return Err(format!("The block {block_number} is not replayed yet"));
+        }
+        let timestamp = match self.blocks.range(..=block_number).next_back() {
+            Some((_, block)) => block.timestamp,
+            None => self.blocks.first_key_value().unwrap().1.timestamp
+        };
+        Ok(BlockHeader {
+            block_number,
+            block_hash: self.hash_of(block_number),
+            parent_hash: block_number.checked_sub(1).map_or(String::from("0x0"), |parent| self.hash_of(parent)),
+            timestamp
+        })
+    }
+
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        let events = self
+            .blocks
+            .range(from_block..=to_block.min(self.released_block()))
+            .flat_map(|(_, block)| block.events.iter().cloned())
+            .collect();
+        Ok(events_to_transactions(events, &self.layout))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use rstest::rstest;
+    use starknet::core::{types::{EmittedEvent, Felt}, utils::cairo_short_string_to_felt};
+
+    use super::ReplaySource;
+    use crate::events::{
+        listener::{receive_event, ListenerEvent},
+        oracle_event::{SpotEntryLayout, SUBMITTED_SPOT_ENTRY},
+        recorder::EventRecorder,
+        source::EventSource
+    };
+
+    fn spot_entry_event(block_number: u64, block_hash: u64, timestamp: u64, price: u128) -> EmittedEvent {
+        EmittedEvent {
+            from_address: Felt::ONE,
+            keys: vec![SUBMITTED_SPOT_ENTRY],
+            data: vec![
+                Felt::from(timestamp),
+                cairo_short_string_to_felt("BINANCE").unwrap(),
+                cairo_short_string_to_felt("PRAGMA").unwrap(),
+                Felt::from(price),
+                cairo_short_string_to_felt("BTC/USD").unwrap(),
+                Felt::ZERO
+            ],
+            block_hash: Some(Felt::from(block_hash)),
+            block_number: Some(block_number),
+            transaction_hash: Felt::from(timestamp)
+        }
+    }
+
+    fn record(name: &str, batches: &[Vec<EmittedEvent>]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("twaplast-replay-{name}-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let recorder = EventRecorder::create(&path).unwrap();
+        for events in batches {
+            recorder.record(events);
+        }
+        path
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn replay_keeps_canonical_events_once() {
+        let path = record("canonical", &[
+            vec![spot_entry_event(10, 0xa, 100, 1), spot_entry_event(11, 0xb, 110, 2)],
+            // Block 11 is orphaned and block 10 is read again.
+            vec![spot_entry_event(10, 0xa, 100, 1), spot_entry_event(11, 0xbb, 111, 3)]
+        ]);
+
+        let replay = ReplaySource::load(&path, 0.0, SpotEntryLayout::default()).unwrap();
+        assert_eq!(replay.block_number().await, Ok(11));
+        let prices: Vec<u128> = replay.get_transactions(0, 11).await.unwrap().iter().map(|t| t.spot_entry.price).collect();
+        assert_eq!(prices, vec![1, 3]);
+
+        let header = replay.block_header(11).await.unwrap();
+        assert_eq!((header.parent_hash.as_str(), header.timestamp), (replay.hash_of(10).as_str(), 111));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn replay_keeps_identical_events_of_a_transaction() {
+        let event = spot_entry_event(10, 0xa, 100, 1);
+        let path = record("identical", &[vec![event.clone(), event.clone()], vec![event.clone(), event]]);
+
+        let replay = ReplaySource::load(&path, 0.0, SpotEntryLayout::default()).unwrap();
+        let indexes: Vec<u64> = replay.get_transactions(0, 10).await.unwrap().iter().map(|t| t.event_index).collect();
+        assert_eq!(indexes, vec![0, 1]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn replay_releases_blocks_at_accelerated_speed() {
+        let path = record("speed", &[vec![
+            spot_entry_event(1, 1, 1_000, 10),
+            spot_entry_event(2, 2, 1_100, 20),
+            spot_entry_event(3, 3, 1_200, 30)
+        ]]);
+
+        // 100 seconds between blocks replayed 1000 times faster: a block every 100ms.
+        let replay = ReplaySource::load(&path, 1_000.0, SpotEntryLayout::default()).unwrap();
+        assert_eq!(replay.block_number().await, Ok(1));
+        assert!(replay.block_header(2).await.is_err());
+
+        let mut receiver = receive_event(replay, vec![String::from("BTC/USD")], None, None, false).await.unwrap();
+        let mut prices = vec![];
+        while prices.len() < 3 {
+            match tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.unwrap() {
+                Some(ListenerEvent::Received(transaction)) => prices.push(transaction.spot_entry.price),
+                Some(_) => continue,
+                None => break
+            }
+        }
+        assert_eq!(prices, vec![10, 20, 30]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use std::{future::Future, time::Duration};
+
+use rand::Rng;
+use tokio::sync::mpsc::Receiver;
+
+use crate::events::{failover::ProviderStatus, source::{BlockHeader, EventSource}, transaction::Transaction};
+
+/// How many times and how long to wait before calling a failing RPC method again.
+#[derive(Clone, Debug)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_retries: u32,
+    pub(crate) initial_delay: Duration,
+    pub(crate) max_delay: Duration
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10)
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with a "full jitter": a random delay between zero and `initial_delay * 2^attempt`,
+    /// capped to `max_delay`, so that several clients do not retry in lockstep.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    /// Calls `operation` until it succeeds or `max_retries` retries have failed.
+    pub(crate) async fn retry<T, F, Fut>(&self, what: &str, mut operation: F) -> Result<T, String>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, String>>
+    {
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_retries => {
+                    let delay = self.delay(attempt);
+                    attempt += 1;
+                    eprintln!("⚠️ {what} failed ({attempt}/{}), retrying in {delay:?} : {e}", self.max_retries);
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(format!("{what} failed after {} retries : {e}", self.max_retries))
+            }
+        }
+    }
+}
+
+/// Wraps a source to retry each of its calls with `RetryPolicy`.
+pub(crate) struct RetryingSource<S> {
+    source: S,
+    policy: RetryPolicy
+}
+
+impl<S: EventSource> RetryingSource<S> {
+    pub(crate) fn new(source: S, policy: RetryPolicy) -> Self {
+        Self { source, policy }
+    }
+}
+
+impl<S: EventSource> EventSource for RetryingSource<S> {
+    async fn block_number(&self) -> Result<u64, String> {
+        self.policy.retry("block_number", || self.source.block_number()).await
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        self.policy.retry("block_header", || self.source.block_header(block_number)).await
+    }
+
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        self.policy.retry("get_transactions", || self.source.get_transactions(from_block, to_block)).await
+    }
+
+    /// Not retried: the pending block is read again at the next poll anyway.
+    async fn get_pending_transactions(&self, block_number: u64) -> Result<Vec<Transaction>, String> {
+        self.source.get_pending_transactions(block_number).await
+    }
+
+    async fn finalized_block_number(&self, latest_block: u64) -> Result<u64, String> {
+        self.policy.retry("finalized_block_number", || self.source.finalized_block_number(latest_block)).await
+    }
+
+    fn providers(&self) -> Vec<ProviderStatus> {
+        self.source.providers()
+    }
+
+    /// Not retried: the listener falls back to polling while the subscription is unavailable.
+    async fn subscribe_new_heads(&self) -> Result<Receiver<u64>, String> {
+        self.source.subscribe_new_heads().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::{AtomicU32, Ordering}, time::Duration};
+
+    use rstest::rstest;
+
+    use super::{RetryPolicy, RetryingSource};
+    use crate::events::source::{EventSource, ScriptedSource};
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy { max_retries, initial_delay: Duration::from_millis(1), max_delay: Duration::from_millis(5) }
+    }
+
+    #[rstest]
+    #[case(0, Duration::from_millis(1))]
+    #[case(2, Duration::from_millis(4))]
+    #[case(10, Duration::from_millis(5))]
+    fn delay_is_capped_exponential(#[case] attempt: u32, #[case] max_expected: Duration) {
+        for _ in 0..100 {
+            assert!(policy(3).delay(attempt) <= max_expected);
+        }
+    }
+
+    #[rstest]
+    #[case(3, Ok(3))]
+    #[case(1, Err(String::from("test failed after 1 retries : attempt 2")))]
+    #[tokio::test]
+    async fn retry_until_success(#[case] max_retries: u32, #[case] expected: Result<u32, String>) {
+        let calls = AtomicU32::new(0);
+        let result = policy(max_retries)
+            .retry("test", || async {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                if call < 3 { Err(format!("attempt {call}")) } else { Ok(call) }
+            })
+            .await;
+        assert_eq!(result, expected);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn retrying_source_hides_transient_failures() {
+        let source = ScriptedSource::new();
+        source.push_block(vec![]);
+        source.fail_next_calls(2);
+
+        let retrying_source = RetryingSource::new(source.clone(), policy(2));
+        assert_eq!(retrying_source.block_number().await, Ok(1));
+
+        source.fail_next_calls(3);
+        assert!(retrying_source.block_number().await.is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use tokio::sync::mpsc::Receiver;
+
+use crate::events::{failover::ProviderStatus, transaction::Transaction};
+
+/// Maximum duration of an RPC request, after which it fails so that a hung provider is retried or failed over.
+pub(crate) const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct BlockHeader {
+    pub(crate) block_number: u64,
+    pub(crate) block_hash: String,
+    pub(crate) parent_hash: String,
+    pub(crate) timestamp: u64
+}
+
+/// A chain the listener can poll to get the emitted `Transaction`s.
+pub(crate) trait EventSource: Send + Sync + 'static {
+    /// Number of the latest block available on the source.
+    fn block_number(&self) -> impl Future<Output = Result<u64, String>> + Send;
+
+    /// Hash, parent hash and timestamp of the block `block_number`.
+    fn block_header(&self, block_number: u64) -> impl Future<Output = Result<BlockHeader, String>> + Send;
+
+    /// Every transaction emitted between `from_block` and `to_block` (both included).
+    fn get_transactions(
+        &self,
+        from_block: u64,
+        to_block: u64
+    ) -> impl Future<Output = Result<Vec<Transaction>, String>> + Send;
+
+    /// Every transaction of the pending block, which will become the block `block_number` once accepted.
+    /// Sources that do not read the pending block return none.
+    fn get_pending_transactions(
+        &self,
+        _block_number: u64
+    ) -> impl Future<Output = Result<Vec<Transaction>, String>> + Send {
+        async { Ok(vec![]) }
+    }
+
+    /// The last final block up to `latest_block`, which is final itself when the source applies no finality.
+    fn finalized_block_number(&self, latest_block: u64) -> impl Future<Output = Result<u64, String>> + Send {
+        async move { Ok(latest_block) }
+    }
+
+    /// Health of the RPC providers behind the source, if it tracks them.
+    fn providers(&self) -> Vec<ProviderStatus> {
+        vec![]
+    }
+
+    /// Number of each new block as soon as it is produced, for the sources that support subscriptions.
+    /// The receiver is closed when the subscription is lost.
+    fn subscribe_new_heads(&self) -> impl Future<Output = Result<Receiver<u64>, String>> + Send {
+        async { Err(String::from("The source does not support subscriptions")) }
+    }
+}
+
+/// Lets several listeners, or a restarted one, share the same source.
+impl<S: EventSource> EventSource for Arc<S> {
+    async fn block_number(&self) -> Result<u64, String> {
+        self.as_ref().block_number().await
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        self.as_ref().block_header(block_number).await
+    }
+
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        self.as_ref().get_transactions(from_block, to_block).await
+    }
+
+    async fn get_pending_transactions(&self, block_number: u64) -> Result<Vec<Transaction>, String> {
+        self.as_ref().get_pending_transactions(block_number).await
+    }
+
+    async fn finalized_block_number(&self, latest_block: u64) -> Result<u64, String> {
+        self.as_ref().finalized_block_number(latest_block).await
+    }
+
+    fn providers(&self) -> Vec<ProviderStatus> {
+        self.as_ref().providers()
+    }
+
+    async fn subscribe_new_heads(&self) -> Result<Receiver<u64>, String> {
+        self.as_ref().subscribe_new_heads().await
+    }
+}
+
+/// Binary searches the first block produced at or after `timestamp`.
+/// Returns the block following the latest one when every block is older.
+pub(crate) async fn first_block_after<S: EventSource>(source: &S, timestamp: u64) -> Result<u64, String> {
+    let (mut low, mut high) = (0, source.block_number().await? + 1);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if source.block_header(middle).await?.timestamp < timestamp {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    Ok(low)
+}
+
+#[cfg(test)]
+pub(crate) use scripted::{scripted_transaction, ScriptedSource};
+
+#[cfg(test)]
+mod scripted {
+    use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+    use tokio::sync::mpsc::Receiver;
+
+    use super::{BlockHeader, EventSource};
+    use crate::events::{spot_entry::SpotEntry, transaction::Transaction, websocket::subscribe_new_heads};
+
+    /// Seconds between two scripted blocks, the block `n` being produced at `n * SCRIPTED_BLOCK_TIME`.
+    pub(crate) const SCRIPTED_BLOCK_TIME: u64 = 10;
+
+    struct ScriptedBlock {
+        hash: String,
+        transactions: Vec<Transaction>
+    }
+
+    struct ScriptedChain {
+        blocks: Vec<ScriptedBlock>,
+        produced_blocks: u64,
+        failing_calls: u32,
+        confirmations: u64,
+        ws_url: Option<String>,
+        pending_transactions: Vec<Transaction>
+    }
+
+    impl ScriptedChain {
+        fn fail_if_requested(&mut self) -> Result<(), String> {
+            if self.failing_calls > 0 {
+                self.failing_calls -= 1;
+                return Err(String::from("Scripted failure"));
+            }
+            Ok(())
+        }
+    }
+
+    /// In-memory chain whose blocks are pushed by the test.
+    /// Clones share the same chain, so a test can keep a handle after giving one to the listener.
+    #[derive(Clone)]
+    pub(crate) struct ScriptedSource {
+        chain: Arc<Mutex<ScriptedChain>>
+    }
+
+    /// Sets the block number of `transactions` and, like the oracle events of a real transaction, indexes the events
+    /// sharing a hash by position.
+    fn in_block(block_number: u64, transactions: Vec<Transaction>) -> Vec<Transaction> {
+        let mut event_counts: HashMap<String, u64> = HashMap::new();
+        transactions
+            .into_iter()
+            .map(|transaction| {
+                let event_count = event_counts.entry(transaction.transaction_hash.clone()).or_default();
+                *event_count += 1;
+                Transaction { block_number, event_index: *event_count - 1, ..transaction }
+            })
+            .collect()
+    }
+
+    impl ScriptedSource {
+        /// Creates a chain made of an empty genesis block.
+        pub(crate) fn new() -> Self {
+            Self {
+                chain: Arc::new(Mutex::new(ScriptedChain {
+                    blocks: vec![ScriptedBlock { hash: String::from("0x0"), transactions: vec![] }],
+                    produced_blocks: 0,
+                    failing_calls: 0,
+                    confirmations: 0,
+                    ws_url: None,
+                    pending_transactions: vec![]
+                }))
+            }
+        }
+
+        /// Appends a new block containing `transactions` and returns its number.
+        pub(crate) fn push_block(&self, transactions: Vec<Transaction>) -> u64 {
+            let mut chain = self.chain.lock().unwrap();
+            let block_number = chain.blocks.len() as u64;
+            // Every produced block gets a distinct hash, even when it replaces an orphaned one.
+            chain.produced_blocks += 1;
+            let hash = format!("0x{:x}", chain.produced_blocks);
+            chain.blocks.push(ScriptedBlock { hash, transactions: in_block(block_number, transactions) });
+            block_number
+        }
+
+        /// Sets the transactions of the pending block, until the next call.
+        pub(crate) fn set_pending(&self, transactions: Vec<Transaction>) {
+            self.chain.lock().unwrap().pending_transactions = transactions;
+        }
+
+        /// Makes the next `calls` calls to the source fail.
+        pub(crate) fn fail_next_calls(&self, calls: u32) {
+            self.chain.lock().unwrap().failing_calls = calls;
+        }
+
+        /// Subscribes to the new blocks through the WebSocket server of `ws_url`, usually a mock one.
+        pub(crate) fn subscribe_via(&self, ws_url: String) {
+            self.chain.lock().unwrap().ws_url = Some(ws_url);
+        }
+
+        /// Only considers final the blocks followed by at least `confirmations` blocks.
+        pub(crate) fn require_confirmations(&self, confirmations: u64) {
+            self.chain.lock().unwrap().confirmations = confirmations;
+        }
+
+        /// Drops every block after `fork_block`; the next pushed blocks build a competing branch.
+        pub(crate) fn reorg(&self, fork_block: u64) {
+            self.chain.lock().unwrap().blocks.truncate(fork_block as usize + 1);
+        }
+    }
+
+    impl EventSource for ScriptedSource {
+        async fn block_number(&self) -> Result<u64, String> {
+            let mut chain = self.chain.lock().unwrap();
+            chain.fail_if_requested()?;
+            Ok(chain.blocks.len() as u64 - 1)
+        }
+
+        async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+            let mut chain = self.chain.lock().unwrap();
+            chain.fail_if_requested()?;
+            let block = chain.blocks.get(block_number as usize).ok_or(format!("Unknown block {block_number}"))?;
+            let parent_hash = match block_number.checked_sub(1) {
+                Some(parent) => chain.blocks[parent as usize].hash.clone(),
+                None => String::from("0x0")
+            };
+            Ok(BlockHeader {
+                block_number,
+                block_hash: block.hash.clone(),
+                parent_hash,
+                timestamp: block_number * SCRIPTED_BLOCK_TIME
+            })
+        }
+
+        async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+            let mut chain = self.chain.lock().unwrap();
+            chain.fail_if_requested()?;
+            Ok(
+                chain
+                    .blocks
+                    .iter()
+                    .skip(from_block as usize)
+                    .take((to_block + 1).saturating_sub(from_block) as usize)
+                    .flat_map(|block| block.transactions.iter())
+                    .cloned()
+                    .collect()
+            )
+        }
+
+        async fn get_pending_transactions(&self, block_number: u64) -> Result<Vec<Transaction>, String> {
+            let mut chain = self.chain.lock().unwrap();
+            chain.fail_if_requested()?;
+            Ok(in_block(block_number, chain.pending_transactions.clone()))
+        }
+
+        async fn finalized_block_number(&self, latest_block: u64) -> Result<u64, String> {
+            let mut chain = self.chain.lock().unwrap();
+            chain.fail_if_requested()?;
+            Ok(latest_block.saturating_sub(chain.confirmations))
+        }
+
+        async fn subscribe_new_heads(&self) -> Result<Receiver<u64>, String> {
+            let ws_url = self.chain.lock().unwrap().ws_url.clone();
+            match ws_url {
+                Some(ws_url) => subscribe_new_heads(&ws_url).await,
+                None => Err(String::from("The source does not support subscriptions"))
+            }
+        }
+    }
+
+    /// Builds a transaction for `pair_id`; the block number is set by `ScriptedSource::push_block`.
+    pub(crate) fn scripted_transaction(pair_id: &str, timestamp: u64, price: u128) -> Transaction {
+        Transaction {
+            block_number: 0,
+            transaction_hash: format!("0x{timestamp:064x}"),
+            event_index: 0,
+            from_address: String::from("0x0"),
+            spot_entry: SpotEntry {
+                timestamp,
+                source: String::from("SCRIPTED"),
+                publisher: String::from("TEST"),
+                price,
+                pair_id: String::from(pair_id),
+                volume: 0
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::{first_block_after, ScriptedSource};
+
+    #[rstest]
+    #[case(0, 0)]
+    #[case(25, 3)]
+    #[case(30, 3)]
+    #[case(50, 5)]
+    #[case(51, 6)]
+    #[tokio::test]
+    async fn first_block_after_timestamp(#[case] timestamp: u64, #[case] expected: u64) {
+        let source = ScriptedSource::new();
+        for _ in 0..5 {
+            source.push_block(vec![]);
+        }
+        assert_eq!(first_block_after(&source, timestamp).await, Ok(expected));
+    }
+}
@@ -1,7 +1,9 @@
 use std::fmt;
 
+use serde::{Deserialize, Serialize};
 use starknet::core::types::Felt;
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct SpotEntry {
     pub(crate) timestamp: u64,
     pub(crate) source: String,
@@ -26,6 +28,10 @@ impl fmt::Display for SpotEntry {
     }
 }
 
+pub(crate) fn felt_to_u64(felt: Felt) -> Result<u64, String> {
+    u64::try_from(felt).map_err(|e| format!("Failed to convert felt to u64: {e}"))
+}
+
 pub(crate) fn felt_to_u128(felt: Felt) -> Result<u128, String> {
     u128::from_str_radix(felt.to_hex_string().trim_start_matches("0x"), 16)
         .map_err(|e| format!("Failed to convert felt to u128: {e}"))
@@ -0,0 +1,439 @@
+use std::{collections::HashMap, fmt, future::Future, str::FromStr, sync::{Arc, Mutex}};
+
+use starknet::{core::{types::{BlockId, BlockStatus, BlockTag, EmittedEvent, EventFilter, Felt, MaybePendingBlockWithTxHashes}, utils::cairo_short_string_to_felt}, providers::{jsonrpc::HttpTransport, JsonRpcClient, Provider, Url}};
+use tokio::sync::mpsc::Receiver;
+
+use crate::events::{
+    finality::{last_accepted_on_l1, Finality},
+    oracle_event::{decode_event, OracleEvent, SpotEntryLayout},
+    recorder::EventRecorder,
+    source::{BlockHeader, EventSource, RPC_TIMEOUT},
+    transaction::Transaction,
+    websocket::subscribe_new_heads
+};
+
+/// Starknet network whose Pragma oracle is followed by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Network {
+    Mainnet,
+    #[default]
+    Sepolia
+}
+
+impl Network {
+    pub(crate) fn infura_url(&self, api_key: &str) -> String {
+        format!("https://starknet-{self}.infura.io/v3/{api_key}")
+    }
+
+    /// Address of the Pragma oracle contract deployed on the network.
+    pub(crate) fn oracle_address(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "0x2a85bd616f912537c50a49a4076db02c00b29b2cdc8a197ce92ed1837fa875b",
+            Network::Sepolia => "0x036031daa264c24520b11d93af622c848b2499b66b41d611bac95e13cfca131a"
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Network::Mainnet => write!(f, "mainnet"),
+            Network::Sepolia => write!(f, "sepolia")
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "sepolia" => Ok(Network::Sepolia),
+            _ => Err(format!("Expected `mainnet` or `sepolia` but got {value}"))
+        }
+    }
+}
+
+/// Reads the spot entry events of a contract through the Starknet JSON-RPC API.
+pub(crate) struct StarknetSource {
+    provider: JsonRpcClient<HttpTransport>,
+    contract_address: Felt,
+    layout: SpotEntryLayout,
+    /// Pairs whose events are requested, every pair being requested when empty or when the pair id is not a key.
+    pair_ids: Vec<Felt>,
+    chunk_size: u64,
+    ws_url: Option<String>,
+    recorder: Option<Arc<EventRecorder>>,
+    reads_pending: bool,
+    finality: Finality,
+    /// Last block known to be accepted on L1, from which the next one is searched.
+    accepted_on_l1: Mutex<u64>
+}
+
+/// Calls `fetch_page` with the continuation token of the previous page until the last page is reached.
+async fn fetch_all_pages<T, F, Fut>(mut fetch_page: F) -> Result<Vec<T>, String>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<(Vec<T>, Option<String>), String>>
+{
+    let mut items = vec![];
+    let mut continuation_token = None;
+    loop {
+        let (page, next_token) = fetch_page(continuation_token).await?;
+        items.extend(page);
+        match next_token {
+            Some(token) => continuation_token = Some(token),
+            None => return Ok(items)
+        }
+    }
+}
+
+fn event_to_transaction(
+    event: EmittedEvent,
+    event_index: u64,
+    layout: &SpotEntryLayout
+) -> Result<Transaction, String> {
+    let entry = layout.decode(&event.keys, &event.data).map_err(|e| match decode_event(&event.keys, &event.data) {
+        Ok(OracleEvent::SpotEntry(_)) | Err(_) => e.to_string(),
+        Ok(other) => format!("Expected a spot entry but got {other}")
+    })?;
+
+    Ok(Transaction {
+        block_number: event.block_number.ok_or("The event is not in an accepted block")?,
+        transaction_hash: event.transaction_hash.to_fixed_hex_string(),
+        event_index,
+        from_address: event.from_address.to_fixed_hex_string(),
+        spot_entry: entry
+    })
+}
+
+/// Decodes the spot entries of `events`, skipping the malformed ones.
+/// The events being in emission order, each one is indexed by its position among the events of its transaction
+/// matching the filter, which is stable as long as the filter is, e.g. across providers and retries.
+pub(crate) fn events_to_transactions(events: Vec<EmittedEvent>, layout: &SpotEntryLayout) -> Vec<Transaction> {
+    let mut event_counts: HashMap<Felt, u64> = HashMap::new();
+    events
+        .into_iter()
+        .filter_map(|event| {
+            let transaction_hash = event.transaction_hash.to_fixed_hex_string();
+            let event_count = event_counts.entry(event.transaction_hash).or_default();
+            let event_index = *event_count;
+            *event_count += 1;
+            event_to_transaction(event, event_index, layout)
+                .inspect_err(|e| eprintln!("⚠️ Skipping the malformed event of {transaction_hash} : {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+impl StarknetSource {
+    /// Connects to `rpc_url` and checks that `contract_addr` is a deployed contract.
+    /// `chunk_size` is the maximum number of events requested per `starknet_getEvents` call.
+    pub(crate) async fn connect(rpc_url: &str, contract_addr: &str, chunk_size: u64) -> Result<Self, String> {
+        let contract_address = Felt::from_hex(contract_addr).map_err(|e| e.to_string())?;
+        let url = Url::parse(rpc_url).map_err(|e| e.to_string())?;
+        let client = reqwest::Client::builder()
+            .timeout(RPC_TIMEOUT)
+            .build()
+            .map_err(|e| format!("Failed to build the HTTP client : {e}"))?;
+        let provider = JsonRpcClient::new(HttpTransport::new_with_client(url, client));
+
+        let block_number = provider
+            .block_number()
+            .await
+            .map_err(|e| format!("Failed to get block number - the contract address might be wrong : {e}"))?;
+
+        match provider.get_class_hash_at(BlockId::Number(block_number), contract_address).await {
+            Ok(class_hash) => {
+                if class_hash == Felt::ZERO {
+                    return Err(String::from("The given contract address does not correspond to a deployed contract"));
+                }
+                println!("✅ The class contract retrieve with success : {:?}", class_hash);
+            }
+            Err(e) => {
+                return Err(format!("Failed to get contract class - the contract address might be wrong : {e}"));
+            }
+        }
+
+        Ok(Self {
+            provider,
+            contract_address,
+            layout: SpotEntryLayout::default(),
+            pair_ids: vec![],
+            chunk_size,
+            ws_url: None,
+            recorder: None,
+            reads_pending: false,
+            finality: Finality::default(),
+            accepted_on_l1: Mutex::new(0)
+        })
+    }
+
+    /// Subscribes to the new blocks through the WebSocket endpoint `ws_url` instead of only polling.
+    pub(crate) fn with_ws_url(self, ws_url: String) -> Self {
+        Self { ws_url: Some(ws_url), ..self }
+    }
+
+    /// Also reads the events of the pending block, before it is accepted.
+    pub(crate) fn with_pending(self) -> Self {
+        Self { reads_pending: true, ..self }
+    }
+
+    /// Only considers final the blocks reaching `finality`.
+    pub(crate) fn with_finality(self, finality: Finality) -> Self {
+        Self { finality, ..self }
+    }
+
+    /// Reads the events of `layout` instead of the `SubmittedSpotEntry` ones.
+    pub(crate) fn with_layout(self, layout: SpotEntryLayout) -> Self {
+        Self { layout, ..self }
+    }
+
+    /// Only requests the events of `pair_ids`, when the layout has the pair id as a key.
+    pub(crate) fn with_pair_ids(self, pair_ids: &[String]) -> Result<Self, String> {
+        let pair_ids = pair_ids
+            .iter()
+            .map(|pair_id| cairo_short_string_to_felt(pair_id).map_err(|e| format!("Invalid pair id {pair_id} : {e}")))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { pair_ids, ..self })
+    }
+
+    /// Records every fetched event with `recorder` before decoding it.
+    pub(crate) fn with_recorder(self, recorder: Arc<EventRecorder>) -> Self {
+        Self { recorder: Some(recorder), ..self }
+    }
+
+    async fn is_accepted_on_l1(&self, block_number: u64) -> Result<bool, String> {
+        match self.provider.get_block_with_tx_hashes(BlockId::Number(block_number)).await {
+            Ok(MaybePendingBlockWithTxHashes::Block(block)) => Ok(block.status == BlockStatus::AcceptedOnL1),
+            Ok(MaybePendingBlockWithTxHashes::PendingBlock(_)) => Ok(false),
+            Err(e) => Err(format!("Failed to get block {block_number} {e}"))
+        }
+    }
+
+    async fn get_events(&self, from_block: BlockId, to_block: BlockId) -> Result<Vec<EmittedEvent>, String> {
+        let filter = EventFilter {
+            from_block: Some(from_block),
+            to_block: Some(to_block),
+            address: Some(self.contract_address),
+            keys: Some(self.layout.filter_keys(&self.pair_ids))
+        };
+
+        fetch_all_pages(|continuation_token| {
+            let filter = filter.clone();
+            async move {
+                self.provider
+                    .get_events(filter, continuation_token, self.chunk_size)
+                    .await
+                    .map(|page| (page.events, page.continuation_token))
+                    .map_err(|e| format!("Failed to fetch events {e}"))
+            }
+        }).await
+    }
+}
+
+impl EventSource for StarknetSource {
+    async fn block_number(&self) -> Result<u64, String> {
+        self.provider.block_number().await.map_err(|e| format!("Failed to get block number {e}"))
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        match self.provider.get_block_with_tx_hashes(BlockId::Number(block_number)).await {
+            Ok(MaybePendingBlockWithTxHashes::Block(block)) => Ok(BlockHeader {
+                block_number: block.block_number,
+                block_hash: block.block_hash.to_fixed_hex_string(),
+                parent_hash: block.parent_hash.to_fixed_hex_string(),
+                timestamp: block.timestamp
+            }),
+            Ok(MaybePendingBlockWithTxHashes::PendingBlock(_)) => Err(format!("Block {block_number} is still pending")),
+            Err(e) => Err(format!("Failed to get block {block_number} {e}"))
+        }
+    }
+
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        let events = self.get_events(BlockId::Number(from_block), BlockId::Number(to_block)).await?;
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&events);
+        }
+        Ok(events_to_transactions(events, &self.layout))
+    }
+
+    /// The pending events are not recorded, their block being recorded once accepted.
+    async fn get_pending_transactions(&self, block_number: u64) -> Result<Vec<Transaction>, String> {
+        if !self.reads_pending {
+            return Ok(vec![]);
+        }
+        let pending = BlockId::Tag(BlockTag::Pending);
+        let events = self.get_events(pending, pending).await?;
+        Ok(events_to_transactions(
+            events.into_iter().map(|event| EmittedEvent { block_number: Some(block_number), ..event }).collect(),
+            &self.layout
+        ))
+    }
+
+    async fn finalized_block_number(&self, latest_block: u64) -> Result<u64, String> {
+        match self.finality {
+            Finality::Confirmations(confirmations) => Ok(latest_block.saturating_sub(confirmations)),
+            Finality::AcceptedOnL1 => {
+                let from_block = *self.accepted_on_l1.lock().unwrap();
+                let block_number =
+                    last_accepted_on_l1(from_block, latest_block, |block_number| self.is_accepted_on_l1(block_number))
+                        .await?;
+                *self.accepted_on_l1.lock().unwrap() = block_number;
+                Ok(block_number)
+            }
+        }
+    }
+
+    async fn subscribe_new_heads(&self) -> Result<Receiver<u64>, String> {
+        match &self.ws_url {
+            Some(ws_url) => subscribe_new_heads(ws_url).await,
+            None => Err(String::from("No WebSocket endpoint is configured"))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::{fixture, rstest};
+
+    use super::{event_to_transaction, events_to_transactions, fetch_all_pages, Network, StarknetSource};
+    use crate::events::oracle_event::{SpotEntryLayout, CHECKPOINT_SPOT_ENTRY, SUBMITTED_SPOT_ENTRY};
+    use starknet::core::{types::{EmittedEvent, Felt}, utils::cairo_short_string_to_felt};
+    use std::env;
+
+    const RPC_BASE_URL: &str = "https://starknet-sepolia.infura.io/v3";
+    const CONTRACT_ADDR: &str = "0x036031daa264c24520b11d93af622c848b2499b66b41d611bac95e13cfca131a";
+
+    #[fixture]
+    fn rpc_url() -> String {
+        let base_url = RPC_BASE_URL;
+        let infura_api_key = env::var("INFURA_API_KEY").expect("INFURA_API_KEY env var must be set");
+        format!("{}/{}", base_url, infura_api_key)
+    }
+
+    #[rstest]
+    #[tokio::test]
+    #[ignore = "needs INFURA_API_KEY and network access"]
+    async fn connect_with_success(rpc_url: String) {
+        assert!(StarknetSource::connect(&rpc_url, CONTRACT_ADDR, 1000).await.is_ok());
+    }
+
+
+    #[tokio::test]
+    #[rstest]
+    #[case(true, "sdszaf")]
+    #[case(false, CONTRACT_ADDR)]
+    #[ignore = "needs INFURA_API_KEY and network access"]
+    async fn connect_failed(
+        #[case] is_rpc_url_ok: bool,
+        #[case] contract_addr: &str,
+        rpc_url: String
+    ) {
+        assert!(StarknetSource::connect(if is_rpc_url_ok {&rpc_url} else {"skjd"}, contract_addr, 1000).await.is_err());
+    }
+
+    #[rstest]
+    #[case("mainnet", Ok(Network::Mainnet))]
+    #[case("Sepolia", Ok(Network::Sepolia))]
+    #[case("goerli", Err(String::from("Expected `mainnet` or `sepolia` but got goerli")))]
+    fn network_from_str(#[case] value: &str, #[case] expected: Result<Network, String>) {
+        assert_eq!(value.parse::<Network>(), expected);
+        if let Ok(network) = expected {
+            let expected_url = format!("https://starknet-{}.infura.io/v3/key", value.to_lowercase());
+            assert_eq!(network.infura_url("key"), expected_url);
+        }
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn fetch_all_pages_follows_continuation_tokens() {
+        let pages = [
+            (vec![1, 2], Some(String::from("a"))),
+            (vec![3, 4], Some(String::from("b"))),
+            (vec![5], None)
+        ];
+        let mut requested_tokens = vec![];
+
+        let items = fetch_all_pages(|continuation_token| {
+            requested_tokens.push(continuation_token.clone());
+            let page = pages[requested_tokens.len() - 1].clone();
+            async move { Ok::<_, String>(page) }
+        }).await;
+
+        assert_eq!(items, Ok(vec![1, 2, 3, 4, 5]));
+        assert_eq!(requested_tokens, vec![None, Some(String::from("a")), Some(String::from("b"))]);
+    }
+
+    fn oracle_event(selector: Felt, data: Vec<Felt>, block_number: Option<u64>) -> EmittedEvent {
+        EmittedEvent {
+            from_address: Felt::from_hex_unchecked(CONTRACT_ADDR),
+            keys: vec![selector],
+            data,
+            block_hash: None,
+            block_number,
+            transaction_hash: Felt::ONE
+        }
+    }
+
+    #[rstest]
+    fn event_to_transaction_decodes_spot_entry() {
+        let data = vec![
+            Felt::from(1_700_000_000u64),
+            cairo_short_string_to_felt("BINANCE").unwrap(),
+            cairo_short_string_to_felt("PRAGMA").unwrap(),
+            Felt::from(6_500_000_000_000u128),
+            cairo_short_string_to_felt("BTC/USD").unwrap(),
+            Felt::ZERO
+        ];
+        let event = oracle_event(SUBMITTED_SPOT_ENTRY, data, Some(12));
+        let transaction = event_to_transaction(event, 0, &SpotEntryLayout::default()).unwrap();
+
+        assert_eq!(transaction.block_number, 12);
+        assert_eq!(transaction.spot_entry.timestamp, 1_700_000_000);
+        assert_eq!(transaction.spot_entry.source, "BINANCE");
+        assert_eq!(transaction.spot_entry.pair_id, "BTC/USD");
+        assert_eq!(transaction.spot_entry.price, 6_500_000_000_000);
+    }
+
+    #[rstest]
+    #[case(SUBMITTED_SPOT_ENTRY, vec![Felt::ONE; 3], Some(12))]
+    #[case(SUBMITTED_SPOT_ENTRY, vec![Felt::ONE; 6], None)]
+    #[case(SUBMITTED_SPOT_ENTRY, vec![Felt::MAX, Felt::ONE, Felt::ONE, Felt::ONE, Felt::ONE, Felt::ONE], Some(12))]
+    #[case(CHECKPOINT_SPOT_ENTRY, vec![Felt::ONE], Some(12))]
+    fn event_to_transaction_rejects_malformed_event(
+        #[case] selector: Felt,
+        #[case] data: Vec<Felt>,
+        #[case] block_number: Option<u64>
+    ) {
+        let event = oracle_event(selector, data, block_number);
+        assert!(event_to_transaction(event, 0, &SpotEntryLayout::default()).is_err());
+    }
+
+    #[rstest]
+    fn events_to_transactions_indexes_events_by_transaction() {
+        let data = vec![
+            Felt::from(1_700_000_000u64),
+            cairo_short_string_to_felt("BINANCE").unwrap(),
+            cairo_short_string_to_felt("PRAGMA").unwrap(),
+            Felt::ONE,
+            cairo_short_string_to_felt("BTC/USD").unwrap(),
+            Felt::ZERO
+        ];
+        let event = |transaction_hash: Felt| EmittedEvent {
+            transaction_hash,
+            ..oracle_event(SUBMITTED_SPOT_ENTRY, data.clone(), Some(12))
+        };
+
+        let events = vec![event(Felt::ONE), event(Felt::TWO), event(Felt::ONE)];
+        let transactions = events_to_transactions(events, &SpotEntryLayout::default());
+        let indexes: Vec<(String, u64)> = transactions
+            .into_iter()
+            .map(|transaction| (transaction.transaction_hash, transaction.event_index))
+            .collect();
+        let (one, two) = (Felt::ONE.to_fixed_hex_string(), Felt::TWO.to_fixed_hex_string());
+        assert_eq!(indexes, vec![(one.clone(), 0), (two, 0), (one, 1)]);
+    }
+}
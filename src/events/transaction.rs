@@ -2,9 +2,14 @@ use crate::events::spot_entry::SpotEntry;
 
 use std::fmt;
 
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub(crate) struct Transaction {
     pub(crate) block_number: u64,
     pub(crate) transaction_hash: String,
+    /// Position of the event among the oracle events emitted by its transaction.
+    pub(crate) event_index: u64,
     pub(crate) from_address: String,
     pub(crate) spot_entry: SpotEntry
 }
@@ -13,9 +18,10 @@ impl fmt::Display for Transaction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{{ {} [{}] <-[{}] : {} }}",
+            "{{ {} [{}#{}] <-[{}] : {} }}",
             self.block_number,
             self.transaction_hash,
+            self.event_index,
             self.from_address,
             self.spot_entry
         )
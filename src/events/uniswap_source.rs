@@ -0,0 +1,262 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use alloy::{
+    primitives::{Address, U256, U512},
+    rpc::types::Log,
+    sol,
+    sol_types::SolEvent
+};
+
+use crate::events::{
+    evm_source::{logs_to_transactions, EvmChain},
+    source::{BlockHeader, EventSource},
+    spot_entry::SpotEntry,
+    transaction::Transaction
+};
+
+sol! {
+    #[sol(rpc)]
+    interface IUniswapV3Pool {
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+
+        event Swap(
+            address indexed sender,
+            address indexed recipient,
+            int256 amount0,
+            int256 amount1,
+            uint160 sqrtPriceX96,
+            uint128 liquidity,
+            int24 tick
+        );
+    }
+
+    #[sol(rpc)]
+    interface IERC20 {
+        function decimals() external view returns (uint8);
+    }
+}
+
+/// Source of the entries computed from the swaps of a Uniswap v3 pool.
+const UNISWAP_V3_SOURCE: &str = "UNISWAP_V3";
+
+/// Decimals of the prices computed from the swaps, as the ones submitted to the oracle.
+const PRICE_DECIMALS: u8 = 8;
+
+/// Price of one base token in quote tokens with `PRICE_DECIMALS` decimals, from the square root of the raw price of
+/// token0 in token1 as a Q64.96 number. The base token is token0, or token1 when `inverted`.
+fn swap_price(sqrt_price_x96: U256, decimals: (u8, u8), inverted: bool) -> Result<u128, String> {
+    let power_of_ten = |exponent: u8| U512::from(10).checked_pow(U512::from(exponent));
+    let squared = U512::from(sqrt_price_x96).checked_pow(U512::from(2));
+    let q192 = Some(U512::from(1) << 192);
+    let (base_decimals, quote_decimals) = if inverted { (decimals.1, decimals.0) } else { decimals };
+    let (raw_base, raw_quote) = if inverted { (q192, squared) } else { (squared, q192) };
+
+    let numerator = raw_base.zip(power_of_ten(PRICE_DECIMALS + base_decimals)).and_then(|(a, b)| a.checked_mul(b));
+    let denominator = raw_quote.zip(power_of_ten(quote_decimals)).and_then(|(a, b)| a.checked_mul(b));
+    let (numerator, denominator) = numerator.zip(denominator).ok_or("Overflow when computing the price")?;
+    if denominator.is_zero() {
+        return Err(String::from("The pool price is zero"));
+    }
+    (numerator / denominator).try_into().map_err(|_| String::from("The price does not fit in 128 bits"))
+}
+
+/// Computes a price from each `Swap` of a Uniswap v3 pool, converted with the decimals of its tokens.
+/// Every entry is attributed to `pair_id`, the pool being its publisher and the amount of base token swapped its
+/// volume.
+pub(crate) struct UniswapV3Source {
+    chain: EvmChain,
+    pool: Address,
+    /// Decimals of token0 and token1.
+    decimals: (u8, u8),
+    inverted: bool,
+    pair_id: String
+}
+
+impl UniswapV3Source {
+    /// Connects to `rpc_url` and reads the decimals of the tokens of `pool`.
+    pub(crate) async fn connect(rpc_url: &str, pool: &str, pair_id: &str) -> Result<Self, String> {
+        let (chain, pool) = EvmChain::connect(rpc_url, pool).await?;
+        let contract = IUniswapV3Pool::new(pool, chain.provider());
+        let call_failed = |e| format!("Failed to read the tokens of the pool {pool} : {e}");
+        let token0 = contract.token0().call().await.map_err(call_failed)?._0;
+        let token1 = contract.token1().call().await.map_err(call_failed)?._0;
+
+        let mut decimals = vec![];
+        for token in [token0, token1] {
+            let call = IERC20::new(token, chain.provider()).decimals().call().await;
+            decimals.push(call.map_err(|e| format!("Failed to read the decimals of {token} : {e}"))?._0);
+        }
+        println!(
+            "🦄 The pool {pool} swaps {token0} ({} decimals) for {token1} ({} decimals)",
+            decimals[0], decimals[1]
+        );
+
+        Ok(Self { chain, pool, decimals: (decimals[0], decimals[1]), inverted: false, pair_id: String::from(pair_id) })
+    }
+
+    /// Prices token1 in token0 instead of token0 in token1.
+    pub(crate) fn with_inverted_price(self) -> Self {
+        Self { inverted: true, ..self }
+    }
+
+    fn swap_to_transaction(&self, log: &Log, event_index: u64, timestamp: u64) -> Result<Transaction, String> {
+        let swap = log.log_decode::<IUniswapV3Pool::Swap>().map_err(|e| format!("Invalid Swap event : {e}"))?;
+        let swap = swap.inner.data;
+        let price = swap_price(U256::from(swap.sqrtPriceX96), self.decimals, self.inverted)?;
+        let base_amount = if self.inverted { swap.amount1 } else { swap.amount0 };
+        let volume =
+            base_amount.unsigned_abs().try_into().map_err(|_| format!("Invalid swapped amount {base_amount}"))?;
+
+        Ok(Transaction {
+            block_number: log.block_number.ok_or("The log is not in a mined block")?,
+            transaction_hash: log.transaction_hash.ok_or("The log has no transaction hash")?.to_string(),
+            event_index,
+            from_address: self.pool.to_string(),
+            spot_entry: SpotEntry {
+                timestamp,
+                source: String::from(UNISWAP_V3_SOURCE),
+                publisher: self.pool.to_string(),
+                price,
+                pair_id: self.pair_id.clone(),
+                volume
+            }
+        })
+    }
+}
+
+impl EventSource for UniswapV3Source {
+    async fn block_number(&self) -> Result<u64, String> {
+        self.chain.block_number().await
+    }
+
+    async fn block_header(&self, block_number: u64) -> Result<BlockHeader, String> {
+        self.chain.block_header(block_number).await
+    }
+
+    /// The swaps are timestamped with their block, read once per block unless the RPC includes it in the logs.
+    async fn get_transactions(&self, from_block: u64, to_block: u64) -> Result<Vec<Transaction>, String> {
+        let logs = self.chain.get_logs(self.pool, IUniswapV3Pool::Swap::SIGNATURE_HASH, from_block, to_block).await?;
+
+        let mut timestamps: HashMap<u64, u64> = HashMap::new();
+        for log in &logs {
+            let Some(block_number) = log.block_number else {
+                continue;
+            };
+            if let Some(timestamp) = log.block_timestamp {
+                timestamps.insert(block_number, timestamp);
+            } else if let Entry::Vacant(entry) = timestamps.entry(block_number) {
+                entry.insert(self.chain.block_header(block_number).await?.timestamp);
+            }
+        }
+
+        Ok(logs_to_transactions(&logs, |log, event_index| {
+            let timestamp = log.block_number.and_then(|block_number| timestamps.get(&block_number).copied());
+            self.swap_to_transaction(log, event_index, timestamp.ok_or("The log is not in a mined block")?)
+        }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{keccak256, Address, I256, U256},
+        sol_types::{SolCall, SolEvent}
+    };
+    use rstest::rstest;
+    use serde_json::{json, Value};
+
+    use super::{swap_price, IUniswapV3Pool, UniswapV3Source, IERC20};
+    use crate::events::{
+        evm_source::{block_hash, mock_evm_rpc, word, MockEvmChain},
+        source::EventSource
+    };
+
+    const POOL: &str = "0x88e6a0c2ddd26feeb64f039a2c41296fcb3f5640";
+    const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+    const WETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+
+    /// `sqrt(price) * 2^96` for a raw price of token0 in token1 given as `numerator / denominator` squared.
+    fn sqrt_price_x96(numerator: u64, denominator: u64) -> U256 {
+        (U256::from(numerator) << 96) / U256::from(denominator)
+    }
+
+    #[rstest]
+    #[case(sqrt_price_x96(2, 1), (6, 6), false, Ok(400_000_000))]
+    #[case(sqrt_price_x96(2, 1), (6, 6), true, Ok(25_000_000))]
+    #[case(sqrt_price_x96(1, 1), (18, 6), false, Ok(100_000_000_000_000_000_000))]
+    #[case(sqrt_price_x96(1, 1), (6, 18), true, Ok(100_000_000_000_000_000_000))]
+    #[case(sqrt_price_x96(1, 1), (6, 18), false, Ok(0))]
+    #[case(U256::ZERO, (6, 18), true, Err(String::from("The pool price is zero")))]
+    fn swap_price_converts_decimals(
+        #[case] sqrt_price: U256,
+        #[case] decimals: (u8, u8),
+        #[case] inverted: bool,
+        #[case] expected: Result<u128, String>
+    ) {
+        assert_eq!(swap_price(sqrt_price, decimals, inverted), expected);
+    }
+
+    fn swap(block_number: u64, amount0: i64, amount1: i64, sqrt_price: U256) -> Value {
+        let topic = |address: &str| word(U256::from_be_slice(address.parse::<Address>().unwrap().as_slice()));
+        let data: Vec<String> = [
+            I256::try_from(amount0).unwrap().into_raw(),
+            I256::try_from(amount1).unwrap().into_raw(),
+            sqrt_price,
+            U256::from(1_000_000u64),
+            U256::ZERO
+        ]
+        .into_iter()
+        .map(|value| word(value).trim_start_matches("0x").to_string())
+        .collect();
+        json!({
+            "address": POOL,
+            "topics": [IUniswapV3Pool::Swap::SIGNATURE_HASH.to_string(), topic(USDC), topic(WETH)],
+            "data": format!("0x{}", data.concat()),
+            "blockNumber": format!("0x{block_number:x}"),
+            "blockHash": block_hash(block_number),
+            "transactionHash": word(U256::from(1_000 + block_number)),
+            "transactionIndex": "0x0",
+            "logIndex": "0x0",
+            "removed": false
+        })
+    }
+
+    fn returned_address(address: &str) -> String {
+        word(U256::from_be_slice(address.parse::<Address>().unwrap().as_slice()))
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn uniswap_source_prices_swaps_with_token_decimals() {
+        let selector = |call: Vec<u8>| format!("0x{}", alloy::hex::encode(call));
+        let url = mock_evm_rpc(MockEvmChain {
+            logs: vec![
+                // 1 WETH = 2000 USDC, i.e. 1 USDC = 5e8 wei of WETH.
+                swap(1, -2_000_000_000, 1_000_000_000_000_000_000, sqrt_price_x96(22_360_679_775, 1_000_000)),
+                swap(2, 4_000_000_000, -2_000_000_000_000_000_000, sqrt_price_x96(22_360_679_775, 1_000_000))
+            ],
+            calls: vec![
+                (String::from(POOL), selector(IUniswapV3Pool::token0Call {}.abi_encode()), returned_address(USDC)),
+                (String::from(POOL), selector(IUniswapV3Pool::token1Call {}.abi_encode()), returned_address(WETH)),
+                (String::from(USDC), selector(IERC20::decimalsCall {}.abi_encode()), word(U256::from(6))),
+                (String::from(WETH), selector(IERC20::decimalsCall {}.abi_encode()), word(U256::from(18)))
+            ]
+        }).await;
+
+        let source = UniswapV3Source::connect(&url, POOL, "ETH/USD").await.unwrap().with_inverted_price();
+        let transactions = source.get_transactions(0, 2).await.unwrap();
+        let entries: Vec<(u64, &str, u128, u128)> = transactions
+            .iter()
+            .map(|t| (t.spot_entry.timestamp, t.spot_entry.source.as_str(), t.spot_entry.price, t.spot_entry.volume))
+            .collect();
+        // The square root being truncated, the price is about 2000 USD with 8 decimals.
+        assert_eq!(entries, vec![
+            (1_700_000_012, "UNISWAP_V3", 199_999_999_999, 1_000_000_000_000_000_000),
+            (1_700_000_024, "UNISWAP_V3", 199_999_999_999, 2_000_000_000_000_000_000)
+        ]);
+        let signature = "Swap(address,address,int256,int256,uint160,uint128,int24)";
+        assert_eq!(keccak256(signature), IUniswapV3Pool::Swap::SIGNATURE_HASH);
+    }
+}
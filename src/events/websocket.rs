@@ -0,0 +1,142 @@
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{self, Receiver};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+const SUBSCRIBE_METHOD: &str = "starknet_subscribeNewHeads";
+const NOTIFICATION_METHOD: &str = "starknet_subscriptionNewHeads";
+
+/// Subscribes to the new blocks through the Starknet JSON-RPC WebSocket API of `ws_url`.
+/// The returned receiver yields the number of each new block and is closed when the connection is lost.
+pub(crate) async fn subscribe_new_heads(ws_url: &str) -> Result<Receiver<u64>, String> {
+    let (mut socket, _) = connect_async(ws_url).await.map_err(|e| format!("Failed to connect to {ws_url} : {e}"))?;
+
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": SUBSCRIBE_METHOD, "params": {} });
+    socket
+        .send(Message::text(request.to_string()))
+        .await
+        .map_err(|e| format!("Failed to subscribe to the new blocks : {e}"))?;
+
+    loop {
+        let message = socket
+            .next()
+            .await
+            .ok_or("The connection was closed before the subscription was confirmed")?
+            .map_err(|e| format!("Failed to subscribe to the new blocks : {e}"))?;
+        let Message::Text(text) = message else { continue };
+        let response: Value = serde_json::from_str(&text).map_err(|e| format!("Invalid subscription response : {e}"))?;
+        if response["id"] != 1 {
+            continue;
+        }
+        if let Some(error) = response.get("error") {
+            return Err(format!("Failed to subscribe to the new blocks : {error}"));
+        }
+        break;
+    }
+
+    let (sender, receiver) = mpsc::channel(64);
+    tokio::spawn(async move {
+        while let Some(Ok(message)) = socket.next().await {
+            let Message::Text(text) = message else { continue };
+            let Ok(notification) = serde_json::from_str::<Value>(&text) else { continue };
+            if notification["method"] != NOTIFICATION_METHOD {
+                continue;
+            }
+            let Some(block_number) = notification["params"]["result"]["block_number"].as_u64() else { continue };
+            if sender.send(block_number).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+
+#[cfg(test)]
+pub(crate) use mock::{mock_websocket_server, MockWebSocket};
+
+#[cfg(test)]
+mod mock {
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio::{net::TcpListener, sync::mpsc};
+    use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+    /// What the mock server does once a client is connected.
+    pub(crate) enum MockWebSocket {
+        /// Confirms the subscription then notifies each block number received on the channel, closing the
+        /// connection when the channel is closed.
+        Heads(mpsc::Receiver<u64>),
+        /// Answers the subscription request with an error.
+        Reject
+    }
+
+    /// Serves one WebSocket connection on a free local port and returns its URL.
+    pub(crate) async fn mock_websocket_server(behaviour: MockWebSocket) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+            let Some(Ok(Message::Text(request))) = socket.next().await else { return };
+            let id = serde_json::from_str::<Value>(&request).unwrap()["id"].clone();
+
+            match behaviour {
+                MockWebSocket::Reject => {
+                    let response = json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": "Method not found" } });
+                    socket.send(Message::text(response.to_string())).await.unwrap();
+                }
+                MockWebSocket::Heads(mut heads) => {
+                    let response = json!({ "jsonrpc": "2.0", "id": id, "result": "0x1" });
+                    socket.send(Message::text(response.to_string())).await.unwrap();
+                    while let Some(block_number) = heads.recv().await {
+                        let notification = json!({
+                            "jsonrpc": "2.0",
+                            "method": "starknet_subscriptionNewHeads",
+                            "params": { "subscription_id": "0x1", "result": { "block_number": block_number } }
+                        });
+                        if socket.send(Message::text(notification.to_string())).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            let _ = socket.close(None).await;
+        });
+        url
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tokio::sync::mpsc;
+
+    use super::{mock_websocket_server, subscribe_new_heads, MockWebSocket};
+
+    #[rstest]
+    #[tokio::test]
+    async fn subscribe_new_heads_forwards_block_numbers() {
+        let (heads, receiver) = mpsc::channel(8);
+        let url = mock_websocket_server(MockWebSocket::Heads(receiver)).await;
+
+        let mut new_heads = subscribe_new_heads(&url).await.unwrap();
+        heads.send(5).await.unwrap();
+        heads.send(6).await.unwrap();
+        assert_eq!(new_heads.recv().await, Some(5));
+        assert_eq!(new_heads.recv().await, Some(6));
+
+        drop(heads);
+        assert_eq!(new_heads.recv().await, None);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn subscribe_new_heads_fails_when_rejected() {
+        let url = mock_websocket_server(MockWebSocket::Reject).await;
+        assert!(subscribe_new_heads(&url).await.is_err());
+        assert!(subscribe_new_heads("ws://127.0.0.1:1").await.is_err());
+    }
+}
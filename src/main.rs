@@ -3,14 +3,38 @@ mod events;
 mod metrics;
 mod server;
 
+use events::{
+    entry_filter::FilteredSource,
+    evm_source::{EvmSource, ANSWER_UPDATED},
+    failover::FailoverSource,
+    finality::Finality,
+    quorum::QuorumSource,
+    recorder::EventRecorder,
+    replay::ReplaySource,
+    oracle_event::{parse_selector, SpotEntryLayout},
+    retry::{RetryPolicy, RetryingSource},
+    source::{first_block_after, EventSource},
+    starknet_source::{Network, StarknetSource},
+    uniswap_source::UniswapV3Source
+};
+use metrics::periods::pair_configs;
 use server::app::server_run_forever;
-use clap::Parser;
+use clap::{ArgGroup, Parser};
+use starknet::providers::Url;
+use std::{path::PathBuf, sync::Arc};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(group(ArgGroup::new("evm_contract").args(["evm_address", "uniswap_pool"]).requires("evm_rpc_url")))]
 struct Args {
-    #[arg(short, long)]
-    id: String,
+    /// Pairs to follow, e.g. `--id=BTC/USD,ETH/USD`; the first one is served by `/data`.
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    id: Vec<String>,
+
+    /// Periods of the TWAPs of every pair, e.g. `--twap-periods=5m,15m,1h,24h`, or of one pair, e.g.
+    /// `--twap-periods=BTC/USD=5m,1h`, the first one being served by `/twap`. Can be repeated, 1h by default.
+    #[arg(long)]
+    twap_periods: Vec<String>,
 
     #[arg(short, long)]
     tcp_addr: String,
@@ -18,24 +42,263 @@ struct Args {
     #[arg(short, long)]
     port: String,
 
-    #[arg(short, long)]
-    api_key: String,
+    /// Starknet network followed through Infura when no `--rpc-url` is given, whose Pragma oracle is read by default.
+    #[arg(long, default_value = "sepolia")]
+    network: Network,
+
+    /// Oracle contract emitting the spot entries, the Pragma oracle of `--network` by default.
+    #[arg(long)]
+    contract_address: Option<String>,
+
+    /// Names or selectors of the contract events carrying the spot entries, all laid out as `SubmittedSpotEntry`.
+    #[arg(long, value_delimiter = ',', default_value = "SubmittedSpotEntry")]
+    event: Vec<String>,
+
+    /// Position among the event keys of the pair id, when the contract indexes it instead of emitting it in the data.
+    /// Only the events of the `--id` pairs are then requested from the RPC.
+    #[arg(long)]
+    pair_id_key: Option<usize>,
+
+    /// Infura API key, used when no `--rpc-url` is given.
+    #[arg(short, long, required_unless_present_any = ["rpc_url", "replay", "evm_rpc_url"])]
+    api_key: Option<String>,
+
+    /// Starknet RPC endpoints by order of preference, e.g. `--rpc-url=https://a,https://b`.
+    #[arg(long, value_delimiter = ',')]
+    rpc_url: Vec<String>,
+
+    /// Starknet WebSocket endpoints notifying the new blocks, in the same order as the RPC endpoints they belong to.
+    /// The new blocks are polled every second when none is given or while the subscription is lost.
+    #[arg(long, value_delimiter = ',')]
+    ws_url: Vec<String>,
+
+    /// EVM RPC endpoints by order of preference, followed instead of Starknet.
+    #[arg(long, value_delimiter = ',', requires = "evm_contract", conflicts_with_all = ["rpc_url", "replay", "record"])]
+    evm_rpc_url: Vec<String>,
+
+    /// EVM contract logging the prices of the first pair of `--id`, e.g. a Chainlink aggregator.
+    #[arg(long)]
+    evm_address: Option<String>,
+
+    /// Signature of the EVM event logging the prices, whose first indexed argument is the price and first
+    /// non-indexed one the update timestamp.
+    #[arg(long, default_value = ANSWER_UPDATED)]
+    evm_event: String,
+
+    /// Uniswap v3 pool whose swaps price the first pair of `--id`, instead of an `--evm-address`.
+    #[arg(long)]
+    uniswap_pool: Option<String>,
+
+    /// Price token1 of the `--uniswap-pool` in token0, e.g. ETH in USDC for the USDC/WETH pool.
+    #[arg(long, requires = "uniswap_pool")]
+    uniswap_inverted: bool,
+
+    /// Number of blocks a provider may lag behind the highest one before failing over to the next one.
+    #[arg(long, default_value_t = 3)]
+    max_lag: u64,
+
+    /// Query every provider and only keep the events reported by at least this many of them, instead of failing over.
+    #[arg(long)]
+    quorum: Option<usize>,
+
+    /// Maximum number of events requested per RPC call, the remaining ones are fetched page by page.
+    #[arg(long, default_value_t = 1000)]
+    chunk_size: u64,
+
+    /// Also read the pending block, whose events are included in the provisional TWAP served by `/provisional`.
+    #[arg(long)]
+    pending: bool,
+
+    /// Number of confirmations, or `l1` for the blocks accepted on L1, after which the events of a block are served
+    /// by `/finalized`. `/data` keeps serving every accepted block.
+    #[arg(long, default_value = "0")]
+    finality: Finality,
+
+    /// File where the last processed block and the TWAP are saved, at most every 30 seconds and on Ctrl-C, to resume
+    /// from it on restart.
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Backfill the events from this block before following the new ones.
+    #[arg(long, conflicts_with = "from_timestamp")]
+    from_block: Option<u64>,
+
+    /// Backfill the events from the first block produced at or after this unix timestamp.
+    #[arg(long)]
+    from_timestamp: Option<u64>,
+
+    /// JSON file of allow and deny lists of publishers and sources, read again whenever it is modified.
+    #[arg(long)]
+    entry_filter: Option<PathBuf>,
+
+    /// Append every raw event fetched from the RPC to this JSON Lines file.
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Replay the events recorded with `--record` instead of following the chain.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// How many times faster than real time the recorded blocks are replayed, 0 replaying them all at once.
+    #[arg(long, default_value_t = 1.0)]
+    replay_speed: f64,
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+/// Only the host is kept, the path of the URL may contain an API key.
+fn provider_name(index: usize, rpc_url: &str) -> String {
+    let host = Url::parse(rpc_url).ok().and_then(|url| url.host_str().map(String::from));
+    format!("#{index} {}", host.unwrap_or_default())
+}
 
-    let rpc_url = "https://starknet-sepolia.infura.io/v3";
-    let contract_addr = "0x036031daa264c24520b11d93af622c848b2499b66b41d611bac95e13cfca131a";
+/// Resolves the block to backfill from, then serves the TWAP computed from the entries of `source` accepted by the
+/// entry filter.
+async fn run<S: EventSource>(args: Args, source: S) {
+    let pairs = match pair_configs(&args.id, &args.twap_periods) {
+        Ok(pairs) => pairs,
+        Err(e) => {
+            eprintln!("❌ {e}");
+            return;
+        }
+    };
+    let source = match FilteredSource::new(source, args.entry_filter) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("❌ {e}");
+            return;
+        }
+    };
+
+    let from_block = match args.from_timestamp {
+        Some(timestamp) => match first_block_after(&source, timestamp).await {
+            Ok(block_number) => {
+                println!("🕰️ The timestamp {timestamp} corresponds to the block n°{block_number}");
+                Some(block_number)
+            }
+            Err(e) => {
+                eprintln!("❌ {e}");
+                return;
+            }
+        },
+        None => args.from_block
+    };
 
     server_run_forever(
         args.tcp_addr.to_string(),
         args.port.to_string(),
-        args.id.to_string(),
-        rpc_url.to_string(),
-        args.api_key.to_string(),
-        contract_addr.to_string(),
+        pairs,
+        source,
+        from_block,
+        args.checkpoint,
         true
     ).await
-}
\ No newline at end of file
+}
+
+/// Serves the TWAP from the `providers` of the same chain, voting with a quorum of them or failing over when needed.
+async fn run_providers<S: EventSource>(args: Args, providers: Vec<(String, S)>) {
+    if providers.is_empty() {
+        eprintln!("❌ No RPC provider is available");
+        return;
+    }
+
+    match args.quorum {
+        Some(quorum) => match QuorumSource::new(providers, quorum) {
+            Ok(source) => run(args, RetryingSource::new(source, RetryPolicy::default())).await,
+            Err(e) => eprintln!("❌ {e}")
+        },
+        None => {
+            let source = RetryingSource::new(FailoverSource::new(providers, args.max_lag), RetryPolicy::default());
+            run(args, source).await
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = Args::parse();
+
+    let selectors = args.event.iter().map(|event| parse_selector(event)).collect::<Result<Vec<_>, _>>();
+    let layout = match selectors.and_then(|selectors| SpotEntryLayout::new(selectors, args.pair_id_key)) {
+        Ok(layout) => layout,
+        Err(e) => {
+            eprintln!("❌ {e}");
+            return;
+        }
+    };
+
+    if let Some(path) = &args.replay {
+        match ReplaySource::load(path, args.replay_speed, layout) {
+            Ok(source) => run(args, source).await,
+            Err(e) => eprintln!("❌ {e}")
+        }
+        return;
+    }
+
+    if let Some(evm_address) = &args.evm_address {
+        let mut providers = vec![];
+        for (index, rpc_url) in args.evm_rpc_url.iter().enumerate() {
+            match EvmSource::connect(rpc_url, evm_address, &args.id[0]).await {
+                Ok(source) => providers.push((provider_name(index, rpc_url), source.with_event_signature(&args.evm_event))),
+                Err(e) => eprintln!("❌ {e}")
+            }
+        }
+        run_providers(args, providers).await;
+        return;
+    }
+
+    if let Some(pool) = &args.uniswap_pool {
+        let mut providers = vec![];
+        for (index, rpc_url) in args.evm_rpc_url.iter().enumerate() {
+            match UniswapV3Source::connect(rpc_url, pool, &args.id[0]).await {
+                Ok(source) => {
+                    let source = if args.uniswap_inverted { source.with_inverted_price() } else { source };
+                    providers.push((provider_name(index, rpc_url), source))
+                }
+                Err(e) => eprintln!("❌ {e}")
+            }
+        }
+        run_providers(args, providers).await;
+        return;
+    }
+
+    let rpc_urls = match &args.api_key {
+        Some(api_key) if args.rpc_url.is_empty() => vec![args.network.infura_url(api_key)],
+        _ => std::mem::take(&mut args.rpc_url)
+    };
+    let contract_addr = args.contract_address.clone().unwrap_or(String::from(args.network.oracle_address()));
+
+    let recorder = match args.record.as_deref().map(EventRecorder::create).transpose() {
+        Ok(recorder) => recorder.map(Arc::new),
+        Err(e) => {
+            eprintln!("❌ {e}");
+            return;
+        }
+    };
+
+    let mut providers = vec![];
+    for (index, rpc_url) in rpc_urls.iter().enumerate() {
+        match StarknetSource::connect(rpc_url, &contract_addr, args.chunk_size).await {
+            Ok(source) => {
+                let source = match source.with_layout(layout.clone()).with_pair_ids(&args.id) {
+                    Ok(source) => source,
+                    Err(e) => {
+                        eprintln!("❌ {e}");
+                        return;
+                    }
+                };
+                let source = match args.ws_url.get(index) {
+                    Some(ws_url) => source.with_ws_url(ws_url.clone()),
+                    None => source
+                };
+                let source = match &recorder {
+                    Some(recorder) => source.with_recorder(recorder.clone()),
+                    None => source
+                };
+                let source = if args.pending { source.with_pending() } else { source };
+                let source = source.with_finality(args.finality);
+                providers.push((provider_name(index, rpc_url), source))
+            }
+            Err(e) => eprintln!("❌ {e}")
+        }
+    }
+    run_providers(args, providers).await
+}
@@ -1,8 +1,11 @@
+pub(crate) mod periods;
 pub(crate) mod twap;
 pub(crate) mod storage;
+pub(crate) mod vwap;
 
 
 pub(crate) trait Metric<MetricType, InputType> {
-    fn update(&mut self, new_value: InputType) -> Result<Option<MetricType>, String>;
+    /// Applies `new_value` and returns the values it completed, if any.
+    fn update(&mut self, new_value: InputType) -> Result<Vec<MetricType>, String>;
     fn current(&self) -> MetricType;
 }
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+
+/// Period of the TWAP of the pairs without any configured period, in seconds.
+pub(crate) const DEFAULT_TWAP_PERIOD: u64 = 3600;
+
+/// A followed pair and the periods of its TWAPs in seconds, the first one being its default TWAP.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PairConfig {
+    pub(crate) pair_id: String,
+    pub(crate) twap_periods: Vec<u64>
+}
+
+#[cfg(test)]
+impl PairConfig {
+    pub(crate) fn new(pair_id: &str) -> Self {
+        Self { pair_id: String::from(pair_id), twap_periods: vec![DEFAULT_TWAP_PERIOD] }
+    }
+}
+
+/// A number of seconds, optionally followed by a unit among `s`, `m`, `h` and `d`, e.g. `15m`.
+pub(crate) fn parse_period(value: &str) -> Result<u64, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(position) => value.split_at(position),
+        None => (value, "s")
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return Err(format!("Unknown unit {unit} in the period {value}"))
+    };
+    match number.parse::<u64>().ok().and_then(|number| number.checked_mul(seconds)) {
+        Some(0) => Err(format!("The period {value} is empty")),
+        Some(period) => Ok(period),
+        None => Err(format!("Invalid period {value}"))
+    }
+}
+
+/// The configuration of each of `pair_ids` from `twap_periods`, each one listing the periods of every pair, e.g.
+/// `5m,1h`, or of a single pair, e.g. `BTC/USD=5m,1h`. The periods of a single pair replace the ones of every pair.
+pub(crate) fn pair_configs(pair_ids: &[String], twap_periods: &[String]) -> Result<Vec<PairConfig>, String> {
+    let parse_periods =
+        |periods: &str| periods.split(',').map(str::trim).map(parse_period).collect::<Result<Vec<_>, _>>();
+
+    let mut default_periods = vec![DEFAULT_TWAP_PERIOD];
+    let mut pair_periods = HashMap::new();
+    for value in twap_periods {
+        match value.split_once('=') {
+            Some((pair_id, periods)) if pair_ids.iter().any(|followed| followed == pair_id) => {
+                pair_periods.insert(pair_id, parse_periods(periods)?);
+            }
+            Some((pair_id, _)) => return Err(format!("The pair {pair_id} of the TWAP periods is not followed")),
+            None => default_periods = parse_periods(value)?
+        }
+    }
+
+    Ok(pair_ids
+        .iter()
+        .map(|pair_id| PairConfig {
+            pair_id: pair_id.clone(),
+            twap_periods: pair_periods.get(pair_id.as_str()).unwrap_or(&default_periods).clone()
+        })
+        .collect())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::{pair_configs, parse_period};
+
+    #[rstest]
+    #[case("90", Ok(90))]
+    #[case("5m", Ok(300))]
+    #[case("1h", Ok(3600))]
+    #[case("24h", Ok(86_400))]
+    #[case("7d", Ok(604_800))]
+    #[case("0m", Err(String::from("The period 0m is empty")))]
+    #[case("5w", Err(String::from("Unknown unit w in the period 5w")))]
+    #[case("h", Err(String::from("Invalid period h")))]
+    fn parse_period_with_unit(#[case] value: &str, #[case] expected: Result<u64, String>) {
+        assert_eq!(parse_period(value), expected);
+    }
+
+    #[rstest]
+    #[case(vec![], Ok(vec![vec![3600], vec![3600]]))]
+    #[case(vec!["5m,15m,1h,24h"], Ok(vec![vec![300, 900, 3600, 86_400], vec![300, 900, 3600, 86_400]]))]
+    #[case(vec!["ETH/USD=1h", "5m"], Ok(vec![vec![300], vec![3600]]))]
+    #[case(vec!["SOL/USD=1h"], Err(String::from("The pair SOL/USD of the TWAP periods is not followed")))]
+    fn pair_configs_by_pair(#[case] twap_periods: Vec<&str>, #[case] expected: Result<Vec<Vec<u64>>, String>) {
+        let pair_ids = vec![String::from("BTC/USD"), String::from("ETH/USD")];
+        let twap_periods: Vec<String> = twap_periods.into_iter().map(String::from).collect();
+
+        let configs = pair_configs(&pair_ids, &twap_periods);
+        if let Ok(configs) = &configs {
+            let pair_ids: Vec<&str> = configs.iter().map(|config| config.pair_id.as_str()).collect();
+            assert_eq!(pair_ids, ["BTC/USD", "ETH/USD"]);
+        }
+        assert_eq!(configs.map(|configs| configs.into_iter().map(|config| config.twap_periods).collect()), expected);
+    }
+}
@@ -1,65 +1,329 @@
-use std::{collections::HashMap, sync::Mutex};
-use crate::metrics::{twap::TwapInput, Metric};
+use std::{collections::{HashMap, VecDeque}, sync::{Mutex, PoisonError}, time::{SystemTime, UNIX_EPOCH}};
+use serde::{Deserialize, Serialize};
+use crate::metrics::{twap::TwapInput, vwap::VwapInput, Metric};
 
-use super::twap::{TwapMetric, TwapValue};
+use super::{
+    twap::{RollingTwapMetric, RollingTwapUndo, TwapMetric, TwapValue},
+    vwap::{VwapMetric, VwapUndo, VwapValue}
+};
 
-pub(crate) trait MetricStorage<KeyType, StorageType> {
-    fn get(&self, key: KeyType) -> Option<StorageType>;
+pub(crate) trait MetricStorage<StorageType> {
     fn last(&self) -> Option<StorageType>;
-    fn insert(&self, key: KeyType, value: StorageType);
+}
+
+/// Number of blocks that can be rolled back after a chain reorganisation.
+const MAX_CHECKPOINTS: usize = 64;
+
+/// Number of seconds a value may be timestamped ahead of the clock, the later ones being skipped.
+const MAX_CLOCK_DRIFT: u64 = 300;
+
+/// Number of prices kept by each rolling TWAP, at most one per second.
+const ROLLING_CAPACITY: usize = 4096;
+
+/// Number of entries kept by each VWAP for its rolling VWAP.
+const VWAP_CAPACITY: usize = 4096;
+
+/// State of the storage before the first value coming from `block_number` was inserted.
+/// The rolling TWAPs and the VWAPs keeping thousands of entries, only the changes of their updates since are kept.
+struct Checkpoint {
+    block_number: u64,
+    twaps: Vec<TwapMetric>,
+    current: Option<TwapValue>,
+    /// The index of the updated rolling TWAP and how to undo its update, by order of update.
+    rolling_undos: Vec<(usize, RollingTwapUndo)>,
+    /// The index of the updated VWAP and how to undo its update, by order of update.
+    vwap_undos: Vec<(usize, VwapUndo)>,
+    /// The period and start of each TWAP and VWAP period closed since.
+    closed_periods: Vec<(u64, u64)>
+}
+
+/// Everything needed to rebuild a `HashMapStorage`, the rollback checkpoints excepted.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct StorageSnapshot {
+    twap_storage: HashMap<u64, HashMap<u64, u128>>,
+    twaps: Vec<TwapMetric>,
+    #[serde(default)]
+    rollings: Vec<RollingTwapMetric>,
+    #[serde(default)]
+    vwap_storage: HashMap<u64, HashMap<u64, u128>>,
+    #[serde(default)]
+    vwaps: Vec<VwapMetric>,
+    current: Option<TwapValue>
 }
 
 pub(crate) struct HashMapStorage {
-    twap_storage: Mutex<HashMap<u64, u128>>,
-    twap: Mutex<TwapMetric>,
-    current: Mutex<Option<TwapValue>>
+    /// The TWAP of each closed period, by TWAP period then by start of the period.
+    twap_storage: Mutex<HashMap<u64, HashMap<u64, u128>>>,
+    /// One TWAP per period, maintained from the same values, the first one being the default.
+    twaps: Mutex<Vec<TwapMetric>>,
+    /// One rolling TWAP per period, over a window as long as the period.
+    rollings: Mutex<Vec<RollingTwapMetric>>,
+    /// The VWAP of each closed period with some volume, by VWAP period then by start of the period.
+    vwap_storage: Mutex<HashMap<u64, HashMap<u64, u128>>>,
+    /// One VWAP per period, maintained from the same entries as the TWAPs.
+    vwaps: Mutex<Vec<VwapMetric>>,
+    current: Mutex<Option<TwapValue>>,
+    checkpoints: Mutex<VecDeque<Checkpoint>>
 }
 
 
 impl HashMapStorage {
-    pub(crate) fn new() -> Self {
+    /// Maintains a TWAP, a rolling TWAP and a VWAP over each of `periods`, in seconds.
+    pub(crate) fn new(periods: &[u64]) -> Self {
+        let mut twaps: Vec<TwapMetric> = vec![];
+        for &period in periods {
+            if twaps.iter().all(|twap| twap.period() != period) {
+                twaps.push(TwapMetric::new(period));
+            }
+        }
+        let rollings = twaps.iter().map(|twap| RollingTwapMetric::new(twap.period(), ROLLING_CAPACITY)).collect();
+        let vwaps: Vec<VwapMetric> = twaps.iter().map(|twap| VwapMetric::new(twap.period(), VWAP_CAPACITY)).collect();
         Self {
-            twap_storage: Mutex::new(HashMap::new()),
-            twap: Mutex::new(TwapMetric::new(3600)),
-            current: Mutex::new(None)
+            twap_storage: Mutex::new(twaps.iter().map(|twap| (twap.period(), HashMap::new())).collect()),
+            twaps: Mutex::new(twaps),
+            rollings: Mutex::new(rollings),
+            vwap_storage: Mutex::new(vwaps.iter().map(|vwap| (vwap.period(), HashMap::new())).collect()),
+            vwaps: Mutex::new(vwaps),
+            current: Mutex::new(None),
+            checkpoints: Mutex::new(VecDeque::with_capacity(MAX_CHECKPOINTS))
         }
     }
-}
 
-impl MetricStorage<u64, u128> for HashMapStorage {
-    fn get(&self, key: u64) -> Option<u128> {
-        match self.twap_storage.lock() {
-            Ok(value) => {
-                value.get(&key).copied()
-            },
-            Err(e) => {
-                eprintln!("HashMapStorage Error while locking for 'get': {}", e);
-                None
+    pub(crate) fn snapshot(&self) -> StorageSnapshot {
+        // Locked first, as in `insert_from_block`, so that no insertion happens while copying.
+        let _checkpoints = self.checkpoints.lock().unwrap_or_else(PoisonError::into_inner);
+        StorageSnapshot {
+            twap_storage: self.twap_storage.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+            twaps: self.twaps.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+            rollings: self.rollings.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+            vwap_storage: self.vwap_storage.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+            vwaps: self.vwaps.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+            current: self.current.lock().unwrap_or_else(PoisonError::into_inner).clone()
+        }
+    }
+
+    /// Only the TWAPs of the periods of this storage are restored, the other ones starting over.
+    pub(crate) fn restore(&self, mut snapshot: StorageSnapshot) {
+        let mut checkpoints = self.checkpoints.lock().unwrap_or_else(PoisonError::into_inner);
+        checkpoints.clear();
+        let mut twaps = self.twaps.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut twap_storage = self.twap_storage.lock().unwrap_or_else(PoisonError::into_inner);
+        for twap in twaps.iter_mut() {
+            let period = twap.period();
+            if let Some(restored) = snapshot.twaps.iter().find(|restored| restored.period() == period) {
+                *twap = restored.clone();
+            }
+            twap_storage.insert(period, snapshot.twap_storage.remove(&period).unwrap_or_default());
+        }
+        for rolling in self.rollings.lock().unwrap_or_else(PoisonError::into_inner).iter_mut() {
+            if let Some(restored) = snapshot.rollings.iter().find(|restored| restored.window() == rolling.window()) {
+                *rolling = restored.clone();
             }
         }
+        let mut vwap_storage = self.vwap_storage.lock().unwrap_or_else(PoisonError::into_inner);
+        for vwap in self.vwaps.lock().unwrap_or_else(PoisonError::into_inner).iter_mut() {
+            let period = vwap.period();
+            if let Some(restored) = snapshot.vwaps.iter().find(|restored| restored.period() == period) {
+                *vwap = restored.clone();
+            }
+            vwap_storage.insert(period, snapshot.vwap_storage.remove(&period).unwrap_or_default());
+        }
+        *self.current.lock().unwrap_or_else(PoisonError::into_inner) = snapshot.current;
     }
 
-    fn last(&self) -> Option<u128> {
-        self.current.lock().unwrap().as_ref().map(|value| value.value)
+    /// Inserts a value coming from the block `block_number`, remembering the previous state so the block can be
+    /// retracted with `rollback`.
+    pub(crate) fn insert_from_block(&self, block_number: u64, key: u64, value: u128, volume: u128) {
+        let mut checkpoints = self.checkpoints.lock().unwrap_or_else(PoisonError::into_inner);
+        if checkpoints.back().is_none_or(|checkpoint| checkpoint.block_number < block_number) {
+            if checkpoints.len() == MAX_CHECKPOINTS {
+                checkpoints.pop_front();
+            }
+            checkpoints.push_back(Checkpoint {
+                block_number,
+                twaps: self.twaps.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+                current: self.current.lock().unwrap_or_else(PoisonError::into_inner).clone(),
+                rolling_undos: vec![],
+                vwap_undos: vec![],
+                closed_periods: vec![]
+            });
+        }
+        self.apply(key, value, volume, checkpoints.back_mut());
     }
 
-    fn insert(&self, key: u64, value: u128) {
-        match self.twap_storage.lock() {
-            Ok(mut guard) => {
-                let mut twap = self.twap.lock().unwrap();
-                let mut current = self.current.lock().unwrap();
-                current.replace(TwapValue { timestamp: key, value });
+    /// Forgets every value inserted from a block after `fork_block`, restoring the TWAP as it was at the fork.
+    pub(crate) fn rollback(&self, fork_block: u64) {
+        let mut checkpoints = self.checkpoints.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(first_orphan) = checkpoints.iter().position(|checkpoint| checkpoint.block_number > fork_block) else {
+            return;
+        };
 
-                if let Some(new_metric) = twap.update(TwapInput{timestamp: key, price: value}).unwrap() {
-                    // A period has been complete, so we add the twap value to the storage.
-                    guard.insert(new_metric.timestamp, new_metric.value);
-                    println!("📥 [{}] One hour complete, adding to the storage : {}", new_metric.timestamp, new_metric.value);
+        let mut twap_storage = self.twap_storage.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut vwap_storage = self.vwap_storage.lock().unwrap_or_else(PoisonError::into_inner);
+        for checkpoint in checkpoints.iter().skip(first_orphan) {
+            for (period, closed_period) in &checkpoint.closed_periods {
+                for storage in [&mut *twap_storage, &mut *vwap_storage] {
+                    if let Some(closed) = storage.get_mut(period) {
+                        closed.remove(closed_period);
+                    }
                 }
             }
-            Err(e) => {
-                eprintln!("HashMapStorage Error while locking for 'inser': {}", e);
+        }
+
+        let mut rollings = self.rollings.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut vwaps = self.vwaps.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut fork_state = None;
+        for checkpoint in checkpoints.drain(first_orphan..).rev() {
+            for (index, undo) in checkpoint.rolling_undos.into_iter().rev() {
+                rollings[index].undo(undo);
             }
+            for (index, undo) in checkpoint.vwap_undos.into_iter().rev() {
+                vwaps[index].undo(undo);
+            }
+            fork_state = Some((checkpoint.twaps, checkpoint.current));
+        }
+        if let Some((twaps, current)) = fork_state {
+            *self.twaps.lock().unwrap_or_else(PoisonError::into_inner) = twaps;
+            *self.current.lock().unwrap_or_else(PoisonError::into_inner) = current;
         }
+        println!("⏪ Rolled back the storage to the block n°{fork_block}");
+    }
+
+    /// The TWAP over `period` of the current period once the pending timestamped values are applied, without storing
+    /// them.
+    pub(crate) fn provisional_twap(&self, period: u64, pending_values: &[(u64, u128)]) -> Option<TwapValue> {
+        if pending_values.is_empty() && self.current.lock().unwrap_or_else(PoisonError::into_inner).is_none() {
+            return None;
+        }
+        let twaps = self.twaps.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut twap = twaps.iter().find(|twap| twap.period() == period)?.clone();
+        drop(twaps);
+        for &(timestamp, price) in pending_values {
+            if let Err(e) = twap.update(TwapInput{timestamp, price}) {
+                eprintln!("⚠️ Skipping the pending value {price} at {timestamp} for the {period}s TWAP : {e}");
+            }
+        }
+        Some(twap.current())
+    }
+
+    /// The periods of the TWAPs, the first one being the default.
+    pub(crate) fn periods(&self) -> Vec<u64> {
+        self.twaps.lock().unwrap_or_else(PoisonError::into_inner).iter().map(TwapMetric::period).collect()
+    }
+
+    /// The TWAP over `period` of the last closed period.
+    pub(crate) fn last_twap(&self, period: u64) -> Option<TwapValue> {
+        let twap_storage = self.twap_storage.lock().unwrap_or_else(PoisonError::into_inner);
+        let (&timestamp, &value) = twap_storage.get(&period)?.iter().max_by_key(|(&timestamp, _)| timestamp)?;
+        Some(TwapValue { timestamp, value })
+    }
+
+    /// The TWAP over the `window` seconds before `now`, for a window as long as one of the periods.
+    pub(crate) fn rolling_twap(&self, window: u64, now: u64) -> Option<TwapValue> {
+        let rollings = self.rollings.lock().unwrap_or_else(PoisonError::into_inner);
+        rollings.iter().find(|rolling| rolling.window() == window)?.at(now)
+    }
+
+    /// The VWAP over `period` of the last closed period with some volume.
+    pub(crate) fn last_vwap(&self, period: u64) -> Option<VwapValue> {
+        let vwap_storage = self.vwap_storage.lock().unwrap_or_else(PoisonError::into_inner);
+        let (&timestamp, &value) = vwap_storage.get(&period)?.iter().max_by_key(|(&timestamp, _)| timestamp)?;
+        Some(VwapValue { timestamp, value })
+    }
+
+    /// The VWAP of the entries of the `period` seconds before `now`.
+    pub(crate) fn rolling_vwap(&self, period: u64, now: u64) -> Option<VwapValue> {
+        let vwaps = self.vwaps.lock().unwrap_or_else(PoisonError::into_inner);
+        vwaps.iter().find(|vwap| vwap.period() == period)?.rolling(now)
+    }
+
+    /// Updates every TWAP with the value and every VWAP with the value and its volume, and adds to `checkpoint` the
+    /// period and start of each TWAP and VWAP period it closed and how to undo the updates.
+    fn apply(&self, key: u64, value: u128, volume: u128, mut checkpoint: Option<&mut Checkpoint>) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        if key > now.saturating_add(MAX_CLOCK_DRIFT) {
+            eprintln!("⚠️ Skipping the value {value} at {key}, more than {MAX_CLOCK_DRIFT}s ahead of the clock");
+            return;
+        }
+        let mut guard = self.twap_storage.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut twaps = self.twaps.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut current = self.current.lock().unwrap_or_else(PoisonError::into_inner);
+        current.replace(TwapValue { timestamp: key, value });
+
+        let mut closed_periods = vec![];
+        for twap in twaps.iter_mut() {
+            let period = twap.period();
+            match twap.update(TwapInput{timestamp: key, price: value}) {
+                Ok(new_metrics) => {
+                    for new_metric in new_metrics {
+                        // A period has been complete, so we add the twap value to the storage.
+                        guard.entry(period).or_default().insert(new_metric.timestamp, new_metric.value);
+                        println!(
+                            "📥 [{}] {period}s period complete, adding to the storage : {}",
+                            new_metric.timestamp, new_metric.value
+                        );
+                        closed_periods.push((period, new_metric.timestamp));
+                    }
+                }
+                Err(e) => eprintln!("⚠️ Skipping the value {value} at {key} for the {period}s TWAP : {e}")
+            }
+        }
+        for (index, rolling) in self.rollings.lock().unwrap_or_else(PoisonError::into_inner).iter_mut().enumerate() {
+            let undo = rolling.update_undoable(TwapInput{timestamp: key, price: value});
+            if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                checkpoint.rolling_undos.push((index, undo));
+            }
+        }
+        let mut vwap_storage = self.vwap_storage.lock().unwrap_or_else(PoisonError::into_inner);
+        for (index, vwap) in self.vwaps.lock().unwrap_or_else(PoisonError::into_inner).iter_mut().enumerate() {
+            let period = vwap.period();
+            match vwap.update_undoable(VwapInput{timestamp: key, price: value, volume}) {
+                Ok((new_metrics, undo)) => {
+                    if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                        checkpoint.vwap_undos.push((index, undo));
+                    }
+                    for new_metric in new_metrics {
+                        vwap_storage.entry(period).or_default().insert(new_metric.timestamp, new_metric.value);
+                        println!(
+                            "📥 [{}] {period}s VWAP period complete, adding to the storage : {}",
+                            new_metric.timestamp, new_metric.value
+                        );
+                        if !closed_periods.contains(&(period, new_metric.timestamp)) {
+                            closed_periods.push((period, new_metric.timestamp));
+                        }
+                    }
+                }
+                Err(e) => eprintln!("⚠️ Skipping the value {value} at {key} for the {period}s VWAP : {e}")
+            }
+        }
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.closed_periods.extend(closed_periods);
+        }
+    }
+}
+
+impl MetricStorage<u128> for HashMapStorage {
+    fn last(&self) -> Option<u128> {
+        self.current.lock().unwrap_or_else(PoisonError::into_inner).as_ref().map(|value| value.value)
+    }
+}
+
+#[cfg(test)]
+impl HashMapStorage {
+    /// The TWAP over `period` of the period starting at `key`, once closed.
+    fn get_twap(&self, period: u64, key: u64) -> Option<u128> {
+        self.twap_storage.lock().unwrap_or_else(PoisonError::into_inner).get(&period)?.get(&key).copied()
+    }
+
+    /// The TWAP of the default period starting at `key`.
+    fn get(&self, key: u64) -> Option<u128> {
+        let period = self.twaps.lock().unwrap_or_else(PoisonError::into_inner).first()?.period();
+        self.get_twap(period, key)
+    }
+
+    /// Inserts a value without any volume, which only counts for the TWAPs.
+    pub(crate) fn insert(&self, key: u64, value: u128) {
+        self.apply(key, value, 0, None);
     }
 }
 
@@ -75,7 +339,7 @@ mod tests {
     #[rstest]
     #[allow(non_snake_case)]
     fn HashMapStorage_get_insert_concurency() {
-        let storage = Arc::new(HashMapStorage::new());
+        let storage = Arc::new(HashMapStorage::new(&[3600]));
         let mut threads = vec![];
 
         let stack = Arc::new(Mutex::new((1..4000).rev().collect::<Vec<u64>>()));
@@ -101,7 +365,7 @@ mod tests {
     #[rstest]
     #[allow(non_snake_case)]
     fn HashMapStorage_last() {
-        let storage = HashMapStorage::new();
+        let storage = HashMapStorage::new(&[3600]);
         storage.insert(1, 10);
         assert_eq!(Some(10), storage.last());
         storage.insert(2, 20);
@@ -117,5 +381,130 @@ mod tests {
         storage.insert(7, 70);
         assert_eq!(Some(70), storage.last());
     }
-}
 
+    #[rstest]
+    #[allow(non_snake_case)]
+    fn HashMapStorage_rollback() {
+        let storage = HashMapStorage::new(&[3600]);
+        storage.insert_from_block(1, 1800, 100, 1);
+        storage.insert_from_block(2, 3000, 120, 1);
+        storage.insert_from_block(3, 3700, 500, 1);
+        storage.insert_from_block(3, 3800, 600, 1);
+        assert!(storage.get(0).is_some());
+
+        storage.rollback(2);
+        assert_eq!(None, storage.get(0));
+        assert_eq!(Some(120), storage.last());
+
+        // The canonical events are applied again on top of the fork block.
+        storage.insert_from_block(3, 3700, 130, 1);
+        assert_eq!(Some(130), storage.last());
+        assert!(storage.get(0).is_some());
+
+        storage.rollback(0);
+        assert_eq!(None, storage.last());
+    }
+
+    #[rstest]
+    #[allow(non_snake_case)]
+    fn HashMapStorage_provisional_twap() {
+        let storage = HashMapStorage::new(&[3600, 60]);
+        assert!(storage.provisional_twap(3600, &[]).is_none());
+        storage.insert(3600, 100);
+        storage.insert(3700, 120);
+
+        let provisional = |pending: &[(u64, u128)]| storage.provisional_twap(3600, pending).map(|twap| twap.value);
+        assert_eq!(provisional(&[]), Some(100));
+        assert_eq!(provisional(&[(3800, 150)]), Some(110));
+        assert_eq!(storage.provisional_twap(60, &[(3800, 150)]).map(|twap| twap.value), Some(120));
+        // The pending values are not applied to the accepted TWAP.
+        assert_eq!(provisional(&[]), Some(100));
+        assert_eq!(Some(120), storage.last());
+    }
+
+    #[rstest]
+    #[allow(non_snake_case)]
+    fn HashMapStorage_skips_values_ahead_of_the_clock() {
+        let storage = HashMapStorage::new(&[60]);
+        storage.insert(1800, 100);
+        storage.insert(u64::MAX - 10, 120);
+        assert_eq!(Some(100), storage.last());
+        assert!(storage.last_twap(60).is_none());
+    }
+
+    #[rstest]
+    #[allow(non_snake_case)]
+    fn HashMapStorage_recovers_poisoned_locks() {
+        let storage = Arc::new(HashMapStorage::new(&[3600]));
+        storage.insert_from_block(1, 1800, 100, 1);
+
+        let failing_storage = Arc::clone(&storage);
+        let failure = std::thread::spawn(move || {
+            let _checkpoints = failing_storage.checkpoints.lock().unwrap();
+            let _twap_storage = failing_storage.twap_storage.lock().unwrap();
+            let _twaps = failing_storage.twaps.lock().unwrap();
+            let _current = failing_storage.current.lock().unwrap();
+            panic!("Simulated failure while inserting");
+        });
+        assert!(failure.join().is_err());
+
+        storage.insert_from_block(2, 3700, 120, 1);
+        assert_eq!(Some(120), storage.last());
+        assert_eq!(Some(100), storage.get(0));
+        storage.rollback(1);
+        assert_eq!(Some(100), storage.last());
+    }
+
+    #[rstest]
+    #[allow(non_snake_case)]
+    fn HashMapStorage_snapshot_restore() {
+        let storage = HashMapStorage::new(&[3600]);
+        storage.insert(1800, 100);
+        storage.insert(3000, 120);
+        storage.insert(3700, 130);
+
+        let restored = HashMapStorage::new(&[3600]);
+        let snapshot = serde_json::to_string(&storage.snapshot()).unwrap();
+        restored.restore(serde_json::from_str(&snapshot).unwrap());
+
+        assert_eq!(storage.get(0), restored.get(0));
+        assert_eq!(Some(130), restored.last());
+
+        storage.insert(7300, 140);
+        restored.insert(7300, 140);
+        assert_eq!(storage.get(3600), restored.get(3600));
+    }
+
+    #[rstest]
+    #[allow(non_snake_case)]
+    fn HashMapStorage_maintains_each_period() {
+        let storage = HashMapStorage::new(&[3600, 300, 3600]);
+        assert_eq!(storage.periods(), vec![3600, 300]);
+        storage.insert_from_block(1, 3600, 100, 1);
+        storage.insert_from_block(2, 3900, 200, 3);
+        storage.insert_from_block(3, 7200, 300, 1);
+
+        assert_eq!(storage.get_twap(300, 3600), Some(100));
+        assert_eq!(storage.get_twap(300, 6900), Some(200));
+        assert_eq!(storage.get_twap(3600, 3600), Some(191));
+        assert_eq!(storage.get(3600), Some(191));
+        assert_eq!(storage.last_twap(300).map(|twap| (twap.timestamp, twap.value)), Some((6900, 200)));
+
+        assert_eq!(storage.rolling_twap(300, 7200).map(|twap| twap.value), Some(200));
+        assert_eq!(storage.rolling_twap(3600, 7200).map(|twap| twap.value), Some(191));
+        assert_eq!(storage.rolling_twap(60, 7200).map(|twap| twap.value), None);
+        assert_eq!(storage.last_vwap(300).map(|vwap| (vwap.timestamp, vwap.value)), Some((3900, 200)));
+        assert_eq!(storage.last_vwap(3600).map(|vwap| (vwap.timestamp, vwap.value)), Some((3600, 175)));
+        assert_eq!(storage.rolling_vwap(300, 7200).map(|vwap| vwap.value), Some(300));
+        assert_eq!(storage.rolling_vwap(3600, 7200).map(|vwap| vwap.value), Some(225));
+
+        storage.rollback(2);
+        assert_eq!(storage.rolling_twap(300, 7200).map(|twap| twap.value), Some(200));
+        assert_eq!(storage.rolling_twap(300, 3900).map(|twap| twap.value), Some(100));
+        assert_eq!(storage.last_twap(300).map(|twap| twap.timestamp), Some(3600));
+        assert_eq!(storage.last_twap(3600).map(|twap| twap.timestamp), None);
+        assert_eq!(storage.last_vwap(300).map(|vwap| vwap.timestamp), Some(3600));
+        assert_eq!(storage.last_vwap(3600).map(|vwap| vwap.timestamp), None);
+        assert_eq!(storage.rolling_vwap(3600, 3900).map(|vwap| vwap.value), Some(175));
+    }
+}
@@ -1,24 +1,23 @@
+use std::collections::VecDeque;
+
+use alloy::primitives::U256;
+use serde::{Deserialize, Serialize};
+
 use super::Metric;
 
-fn weighted_sum(previous_value: u128, current_value: u128, previous_weight: f32, current_weight: f32) -> Result<u128, String> {
-    if previous_weight + current_weight != 1.0 {
-        Err(format!("previous_weight({}) + current_weight({}) != 1.0", previous_weight, current_weight))
-    } else {
-        let scale: u128 = 1_000_000;
-        let prev_weight = (previous_weight * scale as f32).round() as u128;
-        let curr_weight = (current_weight * scale as f32).round() as u128;
-        Ok(
-            previous_value
-                .checked_mul(prev_weight)
-                .and_then(|x| x.checked_div(scale))
-                .ok_or("overflow when calculating previous value")?
-            +
-            current_value
-                .checked_mul(curr_weight)
-                .and_then(|x| x.checked_div(scale))
-                .ok_or("overflow when calculating current_value")?
-        )
-    }
+/// Average price over `seconds` from the sum of the prices multiplied by the seconds they were in effect, rounded
+/// down.
+/// An average of `u128` prices always fits in a `u128`.
+fn average(cumulative: U256, seconds: u64) -> u128 {
+    (cumulative / U256::from(seconds)).to()
+}
+
+/// Number of periods without any update returned at most by one update, the earlier ones being dropped and logged.
+pub(crate) const MAX_CARRIED_PERIODS: u64 = 1024;
+
+/// `price` multiplied by the `seconds` it is in effect, which cannot overflow a `U256`.
+fn weighted(price: u128, seconds: u64) -> U256 {
+    U256::from(price) * U256::from(seconds)
 }
 
 pub (crate) struct TwapInput {
@@ -26,106 +25,247 @@ pub (crate) struct TwapInput {
     pub (crate) price: u128
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct TwapValue {
     pub (crate) timestamp: u64,
     pub (crate) value: u128
 }
 
+/// Time-weighted average price of each period, each price being in effect from its timestamp until the next one, as
+/// with the cumulative price of Uniswap. The last price is carried forward over the periods without any update.
+/// The prices are accumulated exactly as integer price × seconds and the average is only rounded once, down, when it
+/// is read, so the same prices give the same TWAP on every machine.
+#[derive(Clone, Serialize, Deserialize)]
 pub(crate) struct TwapMetric {
     period: u64,
-    current_value: u128,
-    last_timestamp: u64
+    /// Sum of the prices of the current period multiplied by the seconds they were in effect, up to the last update.
+    cumulative: U256,
+    /// Start of the time covered in the current period: the period start, or the first update when later.
+    covered_from: u64,
+    /// The price in effect since the last update.
+    last: Option<TwapValue>
 }
 
 impl TwapMetric {
     pub(crate) fn new(period: u64) -> Self {
         Self {
             period,
-            current_value: 0,
-            last_timestamp: 0
+            cumulative: U256::ZERO,
+            covered_from: 0,
+            last: None
         }
     }
+
+    pub(crate) fn period(&self) -> u64 {
+        self.period
+    }
+
+    fn period_start(&self, timestamp: u64) -> u64 {
+        timestamp.div_euclid(self.period) * self.period
+    }
 }
 
 impl Metric<TwapValue, TwapInput> for TwapMetric {
-    fn update(&mut self, new_value: TwapInput) -> Result<Option<TwapValue>, String> {
-        
-        let previous_value = self.current_value;
-        let current_value = new_value.price;
-        let previous_timestamp = self.last_timestamp;
-        let current_timestamp = new_value.timestamp;
-
-        if previous_timestamp == 0 && current_timestamp == 0 {
-            return Ok(None);
+    /// A price older than the last update but in the same period takes effect at the last update.
+    /// Only the last `MAX_CARRIED_PERIODS` periods without any update before the new one are returned.
+    fn update(&mut self, new_value: TwapInput) -> Result<Vec<TwapValue>, String> {
+        let Some(last) = self.last.clone() else {
+            self.covered_from = new_value.timestamp;
+            self.last = Some(TwapValue{timestamp: new_value.timestamp, value: new_value.price});
+            return Ok(vec![]);
+        };
+
+        let last_hour = self.period_start(last.timestamp);
+        if new_value.timestamp < last_hour {
+            return Err(format!("The timestamp {} is before the current period starting at {last_hour}", new_value.timestamp));
         }
+        let period_end = last_hour
+            .checked_add(self.period)
+            .ok_or_else(|| format!("The period starting at {last_hour} ends after the last timestamp"))?;
 
-        let previous_hour = previous_timestamp.div_euclid(self.period) * self.period;
-        let current_hour = current_timestamp.div_euclid(self.period) * self.period;
-        
-        match previous_hour.cmp(&current_hour) {
-            std::cmp::Ordering::Equal => {
-                let observed_period = current_timestamp - previous_hour;
-                let previous_weight = (previous_timestamp - previous_hour) as f32 / observed_period as f32;
-    
-                self.last_timestamp = current_timestamp;
-                self.current_value = weighted_sum(previous_value, current_value, previous_weight, 1.0 - previous_weight).unwrap();
-                Ok(None)
-            }
+        // The last price is in effect until the new update, closing every period ending before it.
+        let now = new_value.timestamp.max(last.timestamp);
+        self.last = Some(TwapValue{timestamp: now, value: new_value.price});
+        if now < period_end {
+            self.cumulative += weighted(last.value, now - last.timestamp);
+            return Ok(vec![]);
+        }
+
+        self.cumulative += weighted(last.value, period_end - last.timestamp);
+        let closed_average = average(self.cumulative, period_end - self.covered_from);
+        let mut closed = vec![TwapValue{timestamp: last_hour, value: closed_average}];
+        // The periods in between are all closed at once, the last price being in effect over each of them.
+        let new_hour = self.period_start(now);
+        let without_update = (new_hour - period_end) / self.period;
+        let carried = without_update.min(MAX_CARRIED_PERIODS);
+        if without_update > carried {
+            let dropped_until = new_hour - carried * self.period;
+            eprintln!(
+                "⚠️ No {}s TWAP is closed for the {} periods from {period_end} to {dropped_until} without any update",
+                self.period,
+                without_update - carried
+            );
+        }
+        let carried_starts = (1..=carried).rev().map(|index| new_hour - index * self.period);
+        closed.extend(carried_starts.map(|timestamp| TwapValue{timestamp, value: last.value}));
+        self.cumulative = weighted(last.value, now - new_hour);
+        self.covered_from = new_hour;
+        Ok(closed)
+    }
 
-            std::cmp::Ordering::Less => {
-                let previous_weight = (previous_timestamp - previous_hour) as f32 / self.period as f32;
-                let previous_closed_value = weighted_sum(previous_value, current_value, previous_weight, 1.0 - previous_weight).unwrap();
-                
-                self.last_timestamp = current_timestamp;
-                self.current_value = current_value;
-                Ok(Some(TwapValue{timestamp: previous_hour, value: previous_closed_value}))
+    /// The average of the current period up to the last update, or the last price when no time has elapsed in it.
+    fn current(&self) -> TwapValue {
+        match &self.last {
+            Some(last) if last.timestamp > self.covered_from => {
+                TwapValue{timestamp: last.timestamp, value: average(self.cumulative, last.timestamp - self.covered_from)}
             }
+            Some(last) => last.clone(),
+            None => TwapValue{timestamp: 0, value: 0}
+        }
+    }
+}
+
 
-            std::cmp::Ordering::Greater => {
-                Err(format!("previous_hour({}, {}) > current_hour({}, {})", previous_hour, previous_timestamp, current_hour, current_timestamp))
+/// Time-weighted average price over the last `window` seconds as of any time, each price being in effect from its
+/// timestamp until the next one. Only the last `capacity` observations are kept, so the average covers less than the
+/// window once more prices than that were received within it, which its timestamp tells.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct RollingTwapMetric {
+    window: u64,
+    capacity: usize,
+    /// The observations by time, the first one being the price in effect at the start of the window.
+    observations: VecDeque<TwapValue>
+}
+
+impl RollingTwapMetric {
+    pub(crate) fn new(window: u64, capacity: usize) -> Self {
+        Self { window, capacity: capacity.max(1), observations: VecDeque::with_capacity(capacity) }
+    }
+
+    pub(crate) fn window(&self) -> u64 {
+        self.window
+    }
+
+    /// The average over `[now - window, now]` from the observations up to `now`, or `None` before the first one.
+    /// It is timestamped with the start of the time it covers, later than `now - window` when no observation is kept
+    /// from before. The last price is returned when no time has elapsed since then.
+    pub(crate) fn at(&self, now: u64) -> Option<TwapValue> {
+        let start = now.saturating_sub(self.window);
+        let in_effect = self.observations.iter().take_while(|observation| observation.timestamp <= now);
+        let covered_from = self.observations.front().filter(|first| first.timestamp <= now)?.timestamp.max(start);
+
+        let mut cumulative = U256::ZERO;
+        let mut last_price = 0;
+        for (index, observation) in in_effect.enumerate() {
+            let until = match self.observations.get(index + 1) {
+                Some(next) if next.timestamp <= now => next.timestamp,
+                _ => now
+            };
+            let from = observation.timestamp.max(start);
+            if until > from {
+                cumulative += weighted(observation.value, until - from);
             }
+            last_price = observation.value;
+        }
+
+        let value = if now > covered_from { average(cumulative, now - covered_from) } else { last_price };
+        Some(TwapValue{timestamp: covered_from, value})
+    }
+}
+
+/// What an update of a `RollingTwapMetric` replaced and dropped, to undo it.
+pub(crate) struct RollingTwapUndo {
+    replaced: Option<TwapValue>,
+    dropped: Vec<TwapValue>
+}
+
+impl RollingTwapMetric {
+    /// Applies `new_value` as `update` does, and returns how to undo it with `undo`.
+    /// A price older than the last one takes effect at the last one, as with `TwapMetric`.
+    pub(crate) fn update_undoable(&mut self, new_value: TwapInput) -> RollingTwapUndo {
+        let timestamp = match self.observations.back() {
+            Some(last) => new_value.timestamp.max(last.timestamp),
+            None => new_value.timestamp
+        };
+        // A price replaced at the same time was never in effect.
+        let replaced = match self.observations.back() {
+            Some(last) if last.timestamp == timestamp => self.observations.pop_back(),
+            _ => None
+        };
+        self.observations.push_back(TwapValue{timestamp, value: new_value.price});
+
+        let start = timestamp.saturating_sub(self.window);
+        let mut dropped = vec![];
+        while self.observations.len() > self.capacity
+            || self.observations.get(1).is_some_and(|second| second.timestamp <= start)
+        {
+            dropped.extend(self.observations.pop_front());
         }
+        RollingTwapUndo { replaced, dropped }
+    }
+
+    /// Restores the observations as they were before the update that returned `undo`, the later updates being
+    /// undone first.
+    pub(crate) fn undo(&mut self, undo: RollingTwapUndo) {
+        self.observations.pop_back();
+        self.observations.extend(undo.replaced);
+        for observation in undo.dropped.into_iter().rev() {
+            self.observations.push_front(observation);
+        }
+    }
+}
+
+impl Metric<TwapValue, TwapInput> for RollingTwapMetric {
+    /// Nothing is ever complete, the average being read at any time with `at`.
+    fn update(&mut self, new_value: TwapInput) -> Result<Vec<TwapValue>, String> {
+        self.update_undoable(new_value);
+        Ok(vec![])
     }
 
     fn current(&self) -> TwapValue {
-        TwapValue{timestamp: self.last_timestamp, value: self.current_value}
+        let last_timestamp = self.observations.back().map_or(0, |last| last.timestamp);
+        self.at(last_timestamp).unwrap_or(TwapValue{timestamp: 0, value: 0})
     }
 }
 
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
     use rstest::rstest;
-    use crate::metrics::{twap::{TwapInput, TwapMetric}, Metric};
-
-    use super::weighted_sum;
+    use crate::metrics::{twap::{RollingTwapMetric, TwapInput, TwapMetric, MAX_CARRIED_PERIODS}, Metric};
 
     #[rstest]
-    #[case(100, 200, 0.5, 0.5, 150)]
-    #[case(100, 200, 0.0, 1.0, 200)]
-    #[case(100, 200, 1.0, 0.0, 100)]
-    fn test_weighted_sum(
-        #[case] previous_value: u128,
-        #[case] current_value: u128,
-        #[case] previous_weight: f32,
-        #[case] current_weight: f32,
-        #[case] expected: u128
+    #[case(3_000_000_000_000_000_000_000, 3_000_000_000_000_000_000_001, 3_000_000_000_000_000_000_000)]
+    #[case(u128::MAX, u128::MAX, u128::MAX)]
+    #[case(u128::MAX, u128::MAX - 1, u128::MAX - 1)]
+    fn update_keeps_every_digit_of_the_prices(
+        #[case] first_price: u128,
+        #[case] second_price: u128,
+        #[case] expected_closed: u128
     ) {
-        let result = weighted_sum(previous_value, current_value, previous_weight, current_weight).unwrap();
-        assert_eq!(result, expected);
+        let mut twap_metric = TwapMetric::new(3600);
+        twap_metric.update(TwapInput{timestamp: 3601, price: first_price}).unwrap();
+        twap_metric.update(TwapInput{timestamp: 3602, price: second_price}).unwrap();
+        let closed = twap_metric.update(TwapInput{timestamp: 7200, price: second_price}).unwrap();
+
+        // The first price is in effect for one second only, so the average is rounded down to the second price.
+        let closed: Vec<(u64, u128)> = closed.iter().map(|closed| (closed.timestamp, closed.value)).collect();
+        assert_eq!(closed, vec![(3600, expected_closed)]);
     }
 
     #[rstest]
-    #[case(TwapInput{timestamp: 100, price: 100}, TwapInput{timestamp: 200, price: 120}, TwapInput{timestamp: 400, price: 130}, 120, None)]
-    #[case(TwapInput{timestamp: 1800, price: 100}, TwapInput{timestamp: 3599, price: 120}, TwapInput{timestamp: 3800, price: 130}, 130, Some(108))]
-    #[case(TwapInput{timestamp: 1800, price: 100}, TwapInput{timestamp: 3800, price: 120}, TwapInput{timestamp: 4000, price: 130}, 125, None)]
-    #[case(TwapInput{timestamp: 1800, price: 100}, TwapInput{timestamp: 1900, price: 120}, TwapInput{timestamp: 7300, price: 130}, 130, Some(113))]
+    #[case(TwapInput{timestamp: 100, price: 100}, TwapInput{timestamp: 200, price: 120}, TwapInput{timestamp: 400, price: 130}, 113, vec![])]
+    #[case(TwapInput{timestamp: 1800, price: 100}, TwapInput{timestamp: 3599, price: 120}, TwapInput{timestamp: 3800, price: 130}, 120, vec![100])]
+    #[case(TwapInput{timestamp: 1800, price: 100}, TwapInput{timestamp: 3800, price: 120}, TwapInput{timestamp: 4000, price: 130}, 110, vec![])]
+    #[case(TwapInput{timestamp: 1800, price: 100}, TwapInput{timestamp: 1900, price: 120}, TwapInput{timestamp: 7300, price: 130}, 120, vec![118, 120])]
+    #[case(TwapInput{timestamp: 1800, price: 100}, TwapInput{timestamp: 1700, price: 120}, TwapInput{timestamp: 1900, price: 130}, 120, vec![])]
     fn test_update(
         #[case] input_a: TwapInput,
         #[case] input_b: TwapInput,
         #[case] input_c: TwapInput,
         #[case] expected_curr: u128,
-        #[case] expected_prev: Option<u128>
+        #[case] expected_closed: Vec<u128>
     ) {
         let mut twap_metric = TwapMetric::new(3600);
         twap_metric.update(input_a).unwrap();
@@ -134,23 +274,165 @@ mod tests {
 
         let result = twap_metric.current();
         assert_eq!(result.value, expected_curr);
-        
-        match expected_prev {
-            Some(value) => {
-                match output_c {
-                    Some(value_c) => {
-                        assert_eq!(value_c.value, value);
-                    }
-                    None => {
-                        assert_eq!(expected_prev.is_none(), output_c.is_none());
-                    }
-                }
-                
+        assert_eq!(output_c.iter().map(|closed| closed.value).collect::<Vec<_>>(), expected_closed);
+    }
+
+    #[rstest]
+    fn update_closes_the_periods_without_update_at_once() {
+        let mut twap_metric = TwapMetric::new(60);
+        twap_metric.update(TwapInput{timestamp: 30, price: 100}).unwrap();
+        let closed = twap_metric.update(TwapInput{timestamp: u64::MAX - 10, price: 120}).unwrap();
+
+        let last_start = (u64::MAX - 10) / 60 * 60 - 60;
+        assert_eq!(closed.len() as u64, 1 + MAX_CARRIED_PERIODS);
+        assert_eq!((closed[0].timestamp, closed[0].value), (0, 100));
+        assert_eq!(closed.last().map(|closed| (closed.timestamp, closed.value)), Some((last_start, 100)));
+        assert_eq!(closed[1].timestamp, last_start - (MAX_CARRIED_PERIODS - 1) * 60);
+
+        // The period of the last timestamp ends after `u64::MAX`.
+        assert!(twap_metric.update(TwapInput{timestamp: u64::MAX, price: 130}).is_err());
+    }
+
+    #[rstest]
+    fn update_rejects_earlier_period() {
+        let mut twap_metric = TwapMetric::new(3600);
+        twap_metric.update(TwapInput{timestamp: 3700, price: 100}).unwrap();
+        assert!(twap_metric.update(TwapInput{timestamp: 3500, price: 120}).is_err());
+        assert_eq!(twap_metric.current().value, 100);
+    }
+
+    #[rstest]
+    #[case(1000, None)]
+    #[case(1900, Some(183))]
+    #[case(2000, Some(200))]
+    #[case(2300, Some(250))]
+    #[case(2600, Some(300))]
+    fn rolling_at_averages_the_window(#[case] now: u64, #[case] expected: Option<u128>) {
+        let mut rolling = RollingTwapMetric::new(600, 16);
+        rolling.update(TwapInput{timestamp: 1100, price: 100}).unwrap();
+        rolling.update(TwapInput{timestamp: 1200, price: 200}).unwrap();
+        rolling.update(TwapInput{timestamp: 1600, price: 200}).unwrap();
+        rolling.update(TwapInput{timestamp: 1800, price: 100}).unwrap();
+        rolling.update(TwapInput{timestamp: 1900, price: 300}).unwrap();
+
+        assert_eq!(rolling.at(now).map(|twap| twap.value), expected);
+    }
+
+    #[rstest]
+    fn rolling_update_forgets_the_observations_before_the_window() {
+        let mut rolling = RollingTwapMetric::new(60, 2);
+        rolling.update(TwapInput{timestamp: 0, price: 100}).unwrap();
+        rolling.update(TwapInput{timestamp: 30, price: 200}).unwrap();
+        assert_eq!(rolling.current().value, 100);
+
+        // The first price is forgotten beyond the capacity, the second one being in effect since the window start.
+        assert_eq!(rolling.update(TwapInput{timestamp: 90, price: 400}).unwrap().len(), 0);
+        assert_eq!(rolling.current().value, 200);
+        // Only the time since the second price is covered once the window starts before it.
+        assert_eq!(rolling.at(80).map(|twap| (twap.timestamp, twap.value)), Some((30, 200)));
+        assert_eq!(rolling.at(120).map(|twap| twap.value), Some(300));
+
+        // An older price takes effect at the last one, replacing it.
+        rolling.update(TwapInput{timestamp: 10, price: 600}).unwrap();
+        assert_eq!(rolling.at(120).map(|twap| twap.value), Some(400));
+    }
+
+    /// TWAP of every period closed by `updates` and of the current one, reading the price in effect second by second.
+    fn reference_twap(period: u64, updates: &[(u64, u128)]) -> (Vec<(u64, u128)>, u128) {
+        let (first, last) = (updates[0].0, updates[updates.len() - 1].0);
+        let price_at = |second: u64| updates.iter().rev().find(|(timestamp, _)| *timestamp <= second).unwrap().1;
+        let average = |from: u64, to: u64| (from..to).map(price_at).sum::<u128>() / (to - from) as u128;
+
+        let mut closed = vec![];
+        let mut period_start = first / period * period;
+        while period_start + period <= last {
+            closed.push((period_start, average(period_start.max(first), period_start + period)));
+            period_start += period;
+        }
+        let covered_from = period_start.max(first);
+        let current = if last > covered_from { average(covered_from, last) } else { updates[updates.len() - 1].1 };
+        (closed, current)
+    }
+
+    proptest! {
+        #[test]
+        fn undo_restores_the_rolling_twap(
+            updates in prop::collection::vec((0u64..200, 0u128..1000), 1..40),
+            undone in 0usize..40,
+            capacity in 1usize..8
+        ) {
+            let mut rolling = RollingTwapMetric::new(300, capacity);
+            let mut timestamp = 1000;
+            let mut inputs = updates.into_iter().map(|(gap, price)| {
+                // Some prices are older than the last one.
+                timestamp = (timestamp + gap).saturating_sub(50);
+                TwapInput{timestamp, price}
+            });
+            let kept: Vec<_> = inputs.by_ref().take(40usize.saturating_sub(undone)).collect();
+            for input in kept {
+                rolling.update(input).unwrap();
             }
-            None => {
-                assert!(output_c.is_none())
+            let before = serde_json::to_string(&rolling).unwrap();
+
+            let undos: Vec<_> = inputs.map(|input| rolling.update_undoable(input)).collect();
+            for undo in undos.into_iter().rev() {
+                rolling.undo(undo);
             }
+            prop_assert_eq!(serde_json::to_string(&rolling).unwrap(), before);
         }
     }
-}
 
+    proptest! {
+        #[test]
+        fn update_matches_reference_twap(
+            period in 1u64..120,
+            updates in prop::collection::vec((0u64..300, 0..=u128::from(u64::MAX)), 1..30)
+        ) {
+            let mut timestamp = 1_700_000_000;
+            let updates: Vec<(u64, u128)> = updates
+                .into_iter()
+                .map(|(gap, price)| {
+                    timestamp += gap;
+                    (timestamp, price)
+                })
+                .collect();
+
+            let mut twap_metric = TwapMetric::new(period);
+            let mut closed = vec![];
+            for &(timestamp, price) in &updates {
+                closed.extend(twap_metric.update(TwapInput{timestamp, price}).unwrap());
+            }
+
+            let (expected_closed, expected_current) = reference_twap(period, &updates);
+            prop_assert_eq!(closed.iter().map(|closed| (closed.timestamp, closed.value)).collect::<Vec<_>>(), expected_closed);
+            prop_assert_eq!(twap_metric.current().value, expected_current);
+        }
+
+        #[test]
+        fn rolling_at_matches_the_prices_of_the_window(
+            window in 1u64..600,
+            updates in prop::collection::vec((0u64..300, 0..=u128::from(u64::MAX)), 1..30),
+            elapsed in 0u64..300
+        ) {
+            let mut timestamp = 1_700_000_000;
+            let mut rolling = RollingTwapMetric::new(window, 64);
+            let mut prices = vec![];
+            for (gap, price) in updates {
+                timestamp += gap;
+                rolling.update(TwapInput{timestamp, price}).unwrap();
+                prices.retain(|&(previous, _)| previous != timestamp);
+                prices.push((timestamp, price));
+            }
+
+            let now = timestamp + elapsed;
+            let price_at = |second: u64| prices.iter().rev().find(|(timestamp, _)| *timestamp <= second).unwrap().1;
+            let covered_from = prices[0].0.max(now - window);
+            let expected = if now > covered_from {
+                (covered_from..now).map(price_at).sum::<u128>() / (now - covered_from) as u128
+            } else {
+                price_at(now)
+            };
+            prop_assert_eq!(rolling.at(now).map(|twap| twap.value), Some(expected));
+        }
+    }
+}
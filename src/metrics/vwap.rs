@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+
+use alloy::primitives::{U256, U512};
+use serde::{Deserialize, Serialize};
+
+use super::Metric;
+
+pub(crate) struct VwapInput {
+    pub(crate) timestamp: u64,
+    pub(crate) price: u128,
+    pub(crate) volume: u128
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct VwapValue {
+    pub(crate) timestamp: u64,
+    pub(crate) value: u128
+}
+
+/// Sum of the prices multiplied by their volume and sum of the volumes, exact as long as fewer than 2^128 entries are
+/// summed.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct VolumeSums {
+    price_volume: U512,
+    volume: U256
+}
+
+impl VolumeSums {
+    fn add(&mut self, price: u128, volume: u128) {
+        self.price_volume += U512::from(price) * U512::from(volume);
+        self.volume += U256::from(volume);
+    }
+
+    /// The average price weighted by the volumes, rounded down, or `None` without any volume.
+    /// An average of `u128` prices always fits in a `u128`.
+    fn average(&self) -> Option<u128> {
+        (!self.volume.is_zero()).then(|| (self.price_volume / U512::from(self.volume)).to())
+    }
+}
+
+/// Volume-weighted average price of each period, and over the `period` seconds before any time, from the prices and
+/// volumes of the entries. Unlike the TWAP, a period without any volume has no VWAP.
+/// Only the last `capacity` entries are kept for the rolling VWAP, which covers less than the period once more
+/// entries than that were received within it, which its timestamp tells.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct VwapMetric {
+    period: u64,
+    capacity: usize,
+    /// Start of the current period, from the latest entry.
+    period_start: Option<u64>,
+    sums: VolumeSums,
+    /// The entries of the last `period` seconds, by order of reception.
+    entries: VecDeque<(u64, u128, u128)>,
+    /// The latest timestamp of the entries dropped beyond the capacity.
+    #[serde(default)]
+    dropped_until: u64
+}
+
+impl VwapMetric {
+    pub(crate) fn new(period: u64, capacity: usize) -> Self {
+        Self {
+            period,
+            capacity: capacity.max(1),
+            period_start: None,
+            sums: VolumeSums::default(),
+            entries: VecDeque::with_capacity(capacity),
+            dropped_until: 0
+        }
+    }
+
+    pub(crate) fn period(&self) -> u64 {
+        self.period
+    }
+
+    fn period_start(&self, timestamp: u64) -> u64 {
+        timestamp.div_euclid(self.period) * self.period
+    }
+
+    /// The VWAP of the entries timestamped in `]now - period, now]`, or `None` without any volume in it.
+    /// It is timestamped with the start of the time it covers, later than `now - period` when entries of the period
+    /// were dropped beyond the capacity.
+    pub(crate) fn rolling(&self, now: u64) -> Option<VwapValue> {
+        let start = now.saturating_sub(self.period);
+        let covered_from = start.max(self.dropped_until.min(now));
+        let mut sums = VolumeSums::default();
+        for &(timestamp, price, volume) in &self.entries {
+            if timestamp > start && timestamp <= now {
+                sums.add(price, volume);
+            }
+        }
+        sums.average().map(|value| VwapValue{timestamp: covered_from, value})
+    }
+}
+
+/// The state an update of a `VwapMetric` replaced and the entries it dropped, to undo it.
+pub(crate) struct VwapUndo {
+    period_start: Option<u64>,
+    sums: VolumeSums,
+    dropped_until: u64,
+    dropped: Vec<(u64, u128, u128)>
+}
+
+impl VwapMetric {
+    /// Applies `new_value` as `update` does, and returns the VWAP of the period it closed, if any, and how to undo it
+    /// with `undo`.
+    pub(crate) fn update_undoable(&mut self, new_value: VwapInput) -> Result<(Vec<VwapValue>, VwapUndo), String> {
+        let mut undo = VwapUndo {
+            period_start: self.period_start,
+            sums: self.sums.clone(),
+            dropped_until: self.dropped_until,
+            dropped: vec![]
+        };
+        let new_start = self.period_start(new_value.timestamp);
+        let mut closed = vec![];
+        match self.period_start {
+            Some(current_start) if new_start < current_start => {
+                return Err(format!(
+                    "The timestamp {} is before the current period starting at {current_start}",
+                    new_value.timestamp
+                ));
+            }
+            Some(current_start) if new_start > current_start => {
+                closed.extend(self.sums.average().map(|value| VwapValue{timestamp: current_start, value}));
+                self.sums = VolumeSums::default();
+                self.period_start = Some(new_start);
+            }
+            Some(_) => {}
+            None => self.period_start = Some(new_start)
+        }
+        self.sums.add(new_value.price, new_value.volume);
+
+        let latest = self.entries.iter().map(|&(timestamp, _, _)| timestamp).max().unwrap_or(0);
+        self.entries.push_back((new_value.timestamp, new_value.price, new_value.volume));
+        let start = latest.max(new_value.timestamp).saturating_sub(self.period);
+        while self.entries.len() > self.capacity
+            || self.entries.front().is_some_and(|&(timestamp, _, _)| timestamp <= start)
+        {
+            if let Some(entry) = self.entries.pop_front() {
+                if entry.0 > start {
+                    self.dropped_until = self.dropped_until.max(entry.0);
+                }
+                undo.dropped.push(entry);
+            }
+        }
+        Ok((closed, undo))
+    }
+
+    /// Restores the state before the update that returned `undo`, the later updates being undone first.
+    pub(crate) fn undo(&mut self, undo: VwapUndo) {
+        self.entries.pop_back();
+        for entry in undo.dropped.into_iter().rev() {
+            self.entries.push_front(entry);
+        }
+        self.period_start = undo.period_start;
+        self.sums = undo.sums;
+        self.dropped_until = undo.dropped_until;
+    }
+}
+
+impl Metric<VwapValue, VwapInput> for VwapMetric {
+    /// Returns the VWAP of the period closed by an entry of a later period, if it had any volume.
+    fn update(&mut self, new_value: VwapInput) -> Result<Vec<VwapValue>, String> {
+        self.update_undoable(new_value).map(|(closed, _)| closed)
+    }
+
+    /// The VWAP of the current period so far, or 0 without any volume.
+    fn current(&self) -> VwapValue {
+        VwapValue{timestamp: self.period_start.unwrap_or(0), value: self.sums.average().unwrap_or(0)}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::U256;
+    use proptest::prelude::*;
+    use rstest::rstest;
+    use crate::metrics::{vwap::{VwapInput, VwapMetric}, Metric};
+
+    #[rstest]
+    #[case(vec![(100, 100, 1), (200, 200, 3)], vec![], 175)]
+    #[case(vec![(100, 100, 1), (200, 200, 0)], vec![], 100)]
+    #[case(vec![(100, 100, 0), (200, 200, 0)], vec![], 0)]
+    #[case(vec![(100, 100, 1), (3500, 200, 1), (3700, 300, 2)], vec![(0, 150)], 300)]
+    #[case(vec![(100, 100, 0), (3700, 300, 2), (3650, 600, 2)], vec![], 450)]
+    #[case(vec![(100, u128::MAX, u128::MAX), (200, u128::MAX - 1, u128::MAX)], vec![], u128::MAX - 1)]
+    fn update_weights_prices_by_volume(
+        #[case] entries: Vec<(u64, u128, u128)>,
+        #[case] expected_closed: Vec<(u64, u128)>,
+        #[case] expected_current: u128
+    ) {
+        let mut vwap_metric = VwapMetric::new(3600, 16);
+        let mut closed = vec![];
+        for (timestamp, price, volume) in entries {
+            closed.extend(vwap_metric.update(VwapInput{timestamp, price, volume}).unwrap());
+        }
+
+        assert_eq!(closed.iter().map(|closed| (closed.timestamp, closed.value)).collect::<Vec<_>>(), expected_closed);
+        assert_eq!(vwap_metric.current().value, expected_current);
+    }
+
+    #[rstest]
+    fn update_rejects_earlier_period() {
+        let mut vwap_metric = VwapMetric::new(3600, 16);
+        vwap_metric.update(VwapInput{timestamp: 3700, price: 100, volume: 1}).unwrap();
+        assert!(vwap_metric.update(VwapInput{timestamp: 3500, price: 120, volume: 1}).is_err());
+        assert_eq!(vwap_metric.current().value, 100);
+    }
+
+    #[rstest]
+    #[case(1000, None)]
+    #[case(1100, Some(100))]
+    #[case(1700, Some(266))]
+    #[case(1750, Some(300))]
+    #[case(1800, None)]
+    fn rolling_weights_the_entries_of_the_window(#[case] now: u64, #[case] expected: Option<u128>) {
+        let mut vwap_metric = VwapMetric::new(600, 16);
+        vwap_metric.update(VwapInput{timestamp: 1100, price: 100, volume: 1}).unwrap();
+        vwap_metric.update(VwapInput{timestamp: 1150, price: 200, volume: 1}).unwrap();
+        vwap_metric.update(VwapInput{timestamp: 1200, price: 300, volume: 2}).unwrap();
+
+        assert_eq!(vwap_metric.rolling(now).map(|vwap| vwap.value), expected);
+    }
+
+    #[rstest]
+    fn rolling_tells_the_entries_dropped_beyond_the_capacity() {
+        let mut vwap_metric = VwapMetric::new(600, 2);
+        vwap_metric.update(VwapInput{timestamp: 1100, price: 100, volume: 1}).unwrap();
+        vwap_metric.update(VwapInput{timestamp: 1150, price: 200, volume: 1}).unwrap();
+        assert_eq!(vwap_metric.rolling(1200).map(|vwap| (vwap.timestamp, vwap.value)), Some((600, 150)));
+
+        vwap_metric.update(VwapInput{timestamp: 1200, price: 300, volume: 2}).unwrap();
+        assert_eq!(vwap_metric.rolling(1200).map(|vwap| (vwap.timestamp, vwap.value)), Some((1100, 266)));
+        assert_eq!(vwap_metric.rolling(1750).map(|vwap| (vwap.timestamp, vwap.value)), Some((1150, 300)));
+    }
+
+    proptest! {
+        #[test]
+        fn undo_restores_the_vwap(
+            updates in prop::collection::vec((0u64..400, 0u128..1000, 0u128..10), 1..40),
+            undone in 0usize..40,
+            capacity in 1usize..8
+        ) {
+            let mut vwap_metric = VwapMetric::new(300, capacity);
+            let mut timestamp = 1000;
+            let mut inputs = updates.into_iter().map(|(gap, price, volume)| {
+                timestamp += gap;
+                VwapInput{timestamp, price, volume}
+            });
+            let kept: Vec<_> = inputs.by_ref().take(40usize.saturating_sub(undone)).collect();
+            for input in kept {
+                vwap_metric.update(input).unwrap();
+            }
+            let before = serde_json::to_string(&vwap_metric).unwrap();
+
+            let undos: Vec<_> = inputs.map(|input| vwap_metric.update_undoable(input).unwrap().1).collect();
+            for undo in undos.into_iter().rev() {
+                vwap_metric.undo(undo);
+            }
+            prop_assert_eq!(serde_json::to_string(&vwap_metric).unwrap(), before);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn rolling_matches_the_entries_of_the_window(
+            period in 1u64..600,
+            entries in prop::collection::vec((0u64..300, 0..=u128::from(u64::MAX), 0..=u128::from(u64::MAX)), 1..30),
+            elapsed in 0u64..300
+        ) {
+            let mut timestamp = 1_700_000_000;
+            let mut vwap_metric = VwapMetric::new(period, 64);
+            let entries: Vec<(u64, u128, u128)> = entries
+                .into_iter()
+                .map(|(gap, price, volume)| {
+                    timestamp += gap;
+                    (timestamp, price, volume)
+                })
+                .collect();
+            for &(timestamp, price, volume) in &entries {
+                vwap_metric.update(VwapInput{timestamp, price, volume}).unwrap();
+            }
+
+            let now = timestamp + elapsed;
+            let in_window: Vec<_> = entries.iter().filter(|(timestamp, _, _)| *timestamp + period > now).collect();
+            let volume: u128 = in_window.iter().map(|(_, _, volume)| volume).sum();
+            let expected = (volume > 0).then(|| {
+                let price_volume: U256 =
+                    in_window.iter().map(|(_, price, volume)| U256::from(*price) * U256::from(*volume)).sum();
+                (price_volume / U256::from(volume)).to::<u128>()
+            });
+            prop_assert_eq!(vwap_metric.rolling(now).map(|vwap| vwap.value), expected);
+        }
+    }
+}
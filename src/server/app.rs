@@ -1,38 +1,82 @@
-use std::str::FromStr;
-use std::sync::{Arc, Mutex};
-use crate::events::listener::receive_event;
+use std::{collections::HashMap, path::PathBuf, sync::{Arc, Mutex, PoisonError}, time::{Duration, Instant}};
+use crate::events::{dedup::DuplicateFilter, failover::ProviderStatus, finality::FinalityBuffer, listener::{receive_event, ListenerEvent}, retry::RetryPolicy, source::EventSource};
 use crate::server::restapi::create_restapi;
 use crate::{events::transaction, metrics::storage::MetricStorage};
 
 use self::transaction::Transaction;
 
-use crate::metrics::storage::HashMapStorage;
+use crate::metrics::{periods::PairConfig, storage::HashMapStorage, twap::TwapValue, vwap::VwapValue};
+#[cfg(test)]
+use crate::metrics::periods::DEFAULT_TWAP_PERIOD;
 
-use super::signing::generate_keys;
+use super::{checkpoint::{load_checkpoint, save_checkpoint, Checkpoint}, signing::generate_keys};
 
+/// Number of received events remembered to drop the ones received twice.
+const DEDUP_WINDOW: usize = 10_000;
+
+/// Minimum time between two checkpoint saves, the storages holding thousands of entries per period and pair.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
 
 pub(crate) trait AppState: Send + Sync {
     fn identifier(&self) -> &secp256k1::PublicKey;
     fn passkey(&self) -> &secp256k1::SecretKey;
-    fn get_last_value(&self) -> Option<u128>;
+    /// The followed pairs, the first one being served by default.
+    fn pair_ids(&self) -> Vec<String>;
+    fn get_last_value(&self, pair_id: &str) -> Option<u128>;
+    /// The last value among the events of the final blocks only.
+    fn get_finalized_value(&self, pair_id: &str) -> Option<u128>;
+    /// The TWAP over `period` of the current period once the events of the pending block are applied.
+    fn get_provisional_twap(&self, pair_id: &str, period: u64) -> Option<TwapValue>;
+    /// The periods of the TWAPs of the pair, the first one being the default.
+    fn twap_periods(&self, pair_id: &str) -> Vec<u64>;
+    /// The TWAP over `period` of the last closed period.
+    fn get_twap(&self, pair_id: &str, period: u64) -> Option<TwapValue>;
+    /// The TWAP over the `window` seconds before `now`, the window being one of the TWAP periods.
+    fn get_rolling_twap(&self, pair_id: &str, window: u64, now: u64) -> Option<TwapValue>;
+    /// The VWAP over `period` of the last closed period with some volume, the periods being the TWAP ones.
+    fn get_vwap(&self, pair_id: &str, period: u64) -> Option<VwapValue>;
+    /// The VWAP of the entries of the `window` seconds before `now`, the window being one of the TWAP periods.
+    fn get_rolling_vwap(&self, pair_id: &str, window: u64, now: u64) -> Option<VwapValue>;
     fn update(&self, transaction: Transaction);
+    /// Replaces the events of the pending block, which are not applied until their block is accepted.
+    fn update_pending(&self, transactions: Vec<Transaction>);
+    /// Retracts everything received from the blocks after `fork_block`.
+    fn rollback(&self, fork_block: u64);
+    /// Records that every event up to `block_number` has been applied.
+    fn processed(&self, block_number: u64);
+    fn last_processed_block(&self) -> Option<u64>;
+    /// Records that the blocks up to `block_number` are final, applying their events to the finalised values.
+    fn finalized(&self, block_number: u64);
+    fn last_finalized_block(&self) -> Option<u64>;
+    /// Health of the RPC providers the events are read from.
+    fn providers(&self) -> Vec<ProviderStatus>;
 }
 
+#[cfg(test)]
+pub(crate) const MOCK_PAIR_ID: &str = "BTC/USD";
+
+#[cfg(test)]
 pub(crate) struct AppStateMock {
     value: Mutex<Option<u128>>,
+    processed_block: Mutex<Option<u64>>,
+    finalized_block: Mutex<Option<u64>>,
     pub(crate) secret_key: secp256k1::SecretKey,
     pub(crate) public_key: secp256k1::PublicKey
 }
+#[cfg(test)]
 impl AppStateMock {
     pub(crate) fn new(value: Option<u128>) -> Self {
         let (secret_key, public_key) = generate_keys();
         Self {
             value: Mutex::new(value),
+            processed_block: Mutex::new(None),
+            finalized_block: Mutex::new(None),
             public_key,
             secret_key
         }
     }
 }
+#[cfg(test)]
 impl AppState for AppStateMock {
     fn identifier(&self) -> &secp256k1::PublicKey {
         &self.public_key
@@ -40,31 +84,185 @@ impl AppState for AppStateMock {
     fn passkey(&self) -> &secp256k1::SecretKey {
         &self.secret_key
     }
-    fn get_last_value(&self) -> Option<u128> {
-        let value = self.value.lock().unwrap();
-        *value
+    fn pair_ids(&self) -> Vec<String> {
+        vec![String::from(MOCK_PAIR_ID)]
+    }
+    fn get_last_value(&self, pair_id: &str) -> Option<u128> {
+        let value = self.value.lock().unwrap_or_else(PoisonError::into_inner);
+        value.filter(|_| pair_id == MOCK_PAIR_ID)
+    }
+    fn get_finalized_value(&self, pair_id: &str) -> Option<u128> {
+        self.get_last_value(pair_id)
+    }
+    fn get_provisional_twap(&self, pair_id: &str, period: u64) -> Option<TwapValue> {
+        self.get_twap(pair_id, period)
+    }
+    fn twap_periods(&self, _pair_id: &str) -> Vec<u64> {
+        vec![DEFAULT_TWAP_PERIOD]
+    }
+    fn get_twap(&self, pair_id: &str, period: u64) -> Option<TwapValue> {
+        let value = self.get_last_value(pair_id).filter(|_| period == DEFAULT_TWAP_PERIOD)?;
+        Some(TwapValue { timestamp: 0, value })
+    }
+    fn get_rolling_twap(&self, pair_id: &str, window: u64, now: u64) -> Option<TwapValue> {
+        let value = self.get_last_value(pair_id).filter(|_| window == DEFAULT_TWAP_PERIOD)?;
+        Some(TwapValue { timestamp: now, value })
+    }
+    fn get_vwap(&self, pair_id: &str, period: u64) -> Option<VwapValue> {
+        let twap = self.get_twap(pair_id, period)?;
+        Some(VwapValue { timestamp: twap.timestamp, value: twap.value })
+    }
+    fn get_rolling_vwap(&self, pair_id: &str, window: u64, now: u64) -> Option<VwapValue> {
+        let twap = self.get_rolling_twap(pair_id, window, now)?;
+        Some(VwapValue { timestamp: twap.timestamp, value: twap.value })
     }
     fn update(&self, transaction: Transaction) {
-        let mut value = self.value.lock().unwrap();
+        let mut value = self.value.lock().unwrap_or_else(PoisonError::into_inner);
         value.replace(transaction.spot_entry.price);
     }
+    fn update_pending(&self, _transactions: Vec<Transaction>) {}
+    fn rollback(&self, _fork_block: u64) {}
+    fn processed(&self, block_number: u64) {
+        self.processed_block.lock().unwrap_or_else(PoisonError::into_inner).replace(block_number);
+    }
+    fn last_processed_block(&self) -> Option<u64> {
+        *self.processed_block.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn finalized(&self, block_number: u64) {
+        self.finalized_block.lock().unwrap_or_else(PoisonError::into_inner).replace(block_number);
+    }
+    fn last_finalized_block(&self) -> Option<u64> {
+        *self.finalized_block.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn providers(&self) -> Vec<ProviderStatus> {
+        vec![]
+    }
 }
 
 pub(crate) struct AppStateImpl {
-    storage: Arc<HashMapStorage>,
+    pair_ids: Vec<String>,
+    storages: HashMap<String, HashMapStorage>,
+    /// Storages fed with the events of the final blocks only.
+    finalized_storages: HashMap<String, HashMapStorage>,
+    /// Events applied to `storages` whose block is not final yet.
+    unfinalized: Mutex<FinalityBuffer>,
+    finalized_block: Mutex<Option<u64>>,
+    received_events: Mutex<DuplicateFilter>,
+    /// Events of the pending block, dropped once their block is processed.
+    pending: Mutex<Vec<Transaction>>,
+    processed_block: Mutex<Option<u64>>,
+    checkpoint_path: Option<PathBuf>,
+    checkpoint_saved_at: Mutex<Option<Instant>>,
+    provider_statuses: Box<dyn Fn() -> Vec<ProviderStatus> + Send + Sync>,
     pub(crate) secret_key: secp256k1::SecretKey,
     pub(crate) public_key: secp256k1::PublicKey
 }
 impl AppStateImpl {
-    fn new() -> Self {
+    /// Creates one storage per pair with its TWAP periods and keeps the checkpoint saved at `checkpoint_path` up to
+    /// date, resuming from it when `resume` is set.
+    fn new(
+        pairs: Vec<PairConfig>,
+        checkpoint_path: Option<PathBuf>,
+        resume: bool,
+        provider_statuses: Box<dyn Fn() -> Vec<ProviderStatus> + Send + Sync>
+    ) -> Self {
         let (secret_key, public_key) = generate_keys();
+        let new_storages = || -> HashMap<String, HashMapStorage> {
+            pairs.iter().map(|pair| (pair.pair_id.clone(), HashMapStorage::new(&pair.twap_periods))).collect()
+        };
+        let (storages, finalized_storages) = (new_storages(), new_storages());
+        let mut unfinalized = FinalityBuffer::default();
+        let mut processed_block = None;
+        let mut finalized_block = None;
+
+        if let Some(path) = checkpoint_path.as_ref().filter(|_| resume) {
+            match load_checkpoint(path) {
+                Ok(Some(checkpoint)) => {
+                    println!("💾 Resuming from the block n°{}", checkpoint.last_processed_block);
+                    for (pair_id, snapshot) in checkpoint.storages {
+                        if let Some(storage) = storages.get(&pair_id) {
+                            storage.restore(snapshot);
+                        }
+                    }
+                    for (pair_id, snapshot) in checkpoint.finalized_storages {
+                        if let Some(storage) = finalized_storages.get(&pair_id) {
+                            storage.restore(snapshot);
+                        }
+                    }
+                    for transaction in checkpoint.unfinalized {
+                        unfinalized.push(transaction);
+                    }
+                    processed_block = Some(checkpoint.last_processed_block);
+                    finalized_block = checkpoint.last_finalized_block;
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("❌ {e}")
+            }
+        }
+
         Self {
-            storage: Arc::new(HashMapStorage::new()),
+            pair_ids: pairs.into_iter().map(|pair| pair.pair_id).collect(),
+            storages,
+            finalized_storages,
+            unfinalized: Mutex::new(unfinalized),
+            finalized_block: Mutex::new(finalized_block),
+            received_events: Mutex::new(DuplicateFilter::new(DEDUP_WINDOW)),
+            pending: Mutex::new(vec![]),
+            processed_block: Mutex::new(processed_block),
+            checkpoint_path,
+            checkpoint_saved_at: Mutex::new(None),
+            provider_statuses,
             secret_key,
             public_key
         }
     }
 }
+impl AppStateImpl {
+    /// Saves the storages as of the processed block `block_number`, when a checkpoint path is set.
+    fn write_checkpoint(&self, block_number: u64) {
+        let Some(path) = &self.checkpoint_path else {
+            return;
+        };
+        let snapshots = |storages: &HashMap<String, HashMapStorage>| {
+            storages.iter().map(|(pair_id, storage)| (pair_id.clone(), storage.snapshot())).collect()
+        };
+        let checkpoint = Checkpoint {
+            last_processed_block: block_number,
+            storages: snapshots(&self.storages),
+            last_finalized_block: self.last_finalized_block(),
+            finalized_storages: snapshots(&self.finalized_storages),
+            unfinalized: self.unfinalized.lock().unwrap_or_else(PoisonError::into_inner).transactions()
+        };
+        match save_checkpoint(path, &checkpoint) {
+            Ok(()) => *self.checkpoint_saved_at.lock().unwrap_or_else(PoisonError::into_inner) = Some(Instant::now()),
+            Err(e) => eprintln!("❌ {e}")
+        }
+    }
+
+    /// Saves the storages as of the last processed block before exiting, the events of the partially processed
+    /// block being retracted as they are read again on restart.
+    fn shutdown(&self) {
+        if let Some(block_number) = self.last_processed_block() {
+            self.rollback(block_number);
+            self.write_checkpoint(block_number);
+            println!("💾 Saved the block n°{block_number} before exiting");
+        }
+    }
+}
+
+/// Applies the entry of `transaction` to the storage of its pair.
+fn insert_into(storages: &HashMap<String, HashMapStorage>, transaction: &Transaction) {
+    match storages.get(&transaction.spot_entry.pair_id) {
+        Some(storage) => storage.insert_from_block(
+            transaction.block_number,
+            transaction.spot_entry.timestamp,
+            transaction.spot_entry.price,
+            transaction.spot_entry.volume
+        ),
+        None => eprintln!("❌ No storage for the pair {}", transaction.spot_entry.pair_id)
+    }
+}
+
 impl AppState for AppStateImpl {
     fn identifier(&self) -> &secp256k1::PublicKey {
         &self.public_key
@@ -73,28 +271,144 @@ impl AppState for AppStateImpl {
         &self.secret_key
     }
 
-    fn get_last_value(&self) -> Option<u128> {
-        self.storage.last()
+    fn pair_ids(&self) -> Vec<String> {
+        self.pair_ids.clone()
+    }
+
+    fn get_last_value(&self, pair_id: &str) -> Option<u128> {
+        self.storages.get(pair_id)?.last()
+    }
+
+    fn get_finalized_value(&self, pair_id: &str) -> Option<u128> {
+        self.finalized_storages.get(pair_id)?.last()
+    }
+
+    fn get_provisional_twap(&self, pair_id: &str, period: u64) -> Option<TwapValue> {
+        let pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        let values: Vec<(u64, u128)> = pending
+            .iter()
+            .filter(|transaction| transaction.spot_entry.pair_id == pair_id)
+            .map(|transaction| (transaction.spot_entry.timestamp, transaction.spot_entry.price))
+            .collect();
+        self.storages.get(pair_id)?.provisional_twap(period, &values)
+    }
+
+    fn twap_periods(&self, pair_id: &str) -> Vec<u64> {
+        self.storages.get(pair_id).map(HashMapStorage::periods).unwrap_or_default()
+    }
+
+    fn get_twap(&self, pair_id: &str, period: u64) -> Option<TwapValue> {
+        self.storages.get(pair_id)?.last_twap(period)
+    }
+
+    fn get_rolling_twap(&self, pair_id: &str, window: u64, now: u64) -> Option<TwapValue> {
+        self.storages.get(pair_id)?.rolling_twap(window, now)
+    }
+
+    fn get_vwap(&self, pair_id: &str, period: u64) -> Option<VwapValue> {
+        self.storages.get(pair_id)?.last_vwap(period)
+    }
+
+    fn get_rolling_vwap(&self, pair_id: &str, window: u64, now: u64) -> Option<VwapValue> {
+        self.storages.get(pair_id)?.rolling_vwap(window, now)
     }
 
     fn update(&self, transaction: Transaction) {
-        self.storage.insert(transaction.spot_entry.timestamp, transaction.spot_entry.price);
+        if !self.received_events.lock().unwrap_or_else(PoisonError::into_inner).insert(&transaction) {
+            println!(
+                "♻️ [block:{}] Ignoring the event {}#{} received twice",
+                transaction.block_number, transaction.transaction_hash, transaction.event_index
+            );
+            return;
+        }
+        insert_into(&self.storages, &transaction);
+        self.unfinalized.lock().unwrap_or_else(PoisonError::into_inner).push(transaction);
+    }
+
+    fn update_pending(&self, transactions: Vec<Transaction>) {
+        let processed_block = *self.processed_block.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        *pending = transactions;
+        pending.retain(|transaction| processed_block.is_none_or(|block_number| transaction.block_number > block_number));
+    }
+
+    fn rollback(&self, fork_block: u64) {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        for storage in self.storages.values() {
+            storage.rollback(fork_block);
+        }
+        self.received_events.lock().unwrap_or_else(PoisonError::into_inner).rollback(fork_block);
+        self.unfinalized.lock().unwrap_or_else(PoisonError::into_inner).rollback(fork_block);
+        let mut finalized_block = self.finalized_block.lock().unwrap_or_else(PoisonError::into_inner);
+        if finalized_block.is_some_and(|block_number| block_number > fork_block) {
+            eprintln!("⚠️ The final blocks after the block n°{fork_block} have been orphaned");
+            for storage in self.finalized_storages.values() {
+                storage.rollback(fork_block);
+            }
+            finalized_block.replace(fork_block);
+        }
+        let mut processed_block = self.processed_block.lock().unwrap_or_else(PoisonError::into_inner);
+        if processed_block.is_some_and(|block_number| block_number > fork_block) {
+            processed_block.replace(fork_block);
+        }
+    }
+
+    fn processed(&self, block_number: u64) {
+        self.processed_block.lock().unwrap_or_else(PoisonError::into_inner).replace(block_number);
+        // The pending events of an accepted block have been received as such, the others never made it in.
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner).retain(|event| event.block_number > block_number);
+
+        let saved_at = *self.checkpoint_saved_at.lock().unwrap_or_else(PoisonError::into_inner);
+        if saved_at.is_none_or(|saved_at| saved_at.elapsed() >= CHECKPOINT_INTERVAL) {
+            self.write_checkpoint(block_number);
+        }
+    }
+
+    fn last_processed_block(&self) -> Option<u64> {
+        *self.processed_block.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn finalized(&self, block_number: u64) {
+        let mut unfinalized = self.unfinalized.lock().unwrap_or_else(PoisonError::into_inner);
+        for transaction in unfinalized.finalize(block_number) {
+            insert_into(&self.finalized_storages, &transaction);
+        }
+        self.finalized_block.lock().unwrap_or_else(PoisonError::into_inner).replace(block_number);
+    }
+
+    fn last_finalized_block(&self) -> Option<u64> {
+        *self.finalized_block.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn providers(&self) -> Vec<ProviderStatus> {
+        (self.provider_statuses)()
     }
 }
 
-pub(crate) async fn server_run_forever(
+pub(crate) async fn server_run_forever<S: EventSource>(
     tcp_addr: String,
     port: String,
-    pair_id: String,
-    rpc_url: String,
-    api_key: String,
-    contract_addr: String,
+    pairs: Vec<PairConfig>,
+    source: S,
+    from_block: Option<u64>,
+    checkpoint_path: Option<PathBuf>,
     is_verbose: bool
 ) {
     if is_verbose {
         println!("⌛ Starting server");
     }
-    let app_state = Arc::new(AppStateImpl::new());
+    let source = Arc::new(source);
+    let source_providers = Arc::clone(&source);
+    // An explicit `from_block` recomputes the TWAP from scratch instead of resuming from the checkpoint.
+    let pair_ids: Vec<String> = pairs.iter().map(|pair| pair.pair_id.clone()).collect();
+    let app_state = Arc::new(AppStateImpl::new(
+        pairs,
+        checkpoint_path,
+        from_block.is_none(),
+        Box::new(move || source_providers.providers())
+    ));
+    // Resume right after the last block processed before the restart, backfilling the blocks missed meanwhile.
+    let from_block = from_block.or(app_state.last_processed_block().map(|block_number| block_number + 1));
 
     let app_state_restapi = Arc::clone(&app_state);
     let restapi_thread = tokio::spawn(async move {
@@ -112,20 +426,279 @@ pub(crate) async fn server_run_forever(
 
     let app_state_twap = Arc::clone(&app_state);
     let gather_twap_thread = tokio::spawn(async move {
-        let mut receiver = receive_event(&format!("{}/{}", rpc_url, api_key), &contract_addr, &pair_id, None, is_verbose).await.unwrap();
-        
-        let twap_storage_thread = tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
-                while let Some(entry) = receiver.recv().await {
-                    app_state_twap.update(entry);
+        let restart_policy = RetryPolicy::default();
+        let mut restarts = 0;
+        let mut from_block = from_block;
+
+        // Supervises the ingestion: whenever the listener or the storage task stops or panics, the events of the
+        // partially processed blocks are retracted and the listener is restarted right after the last processed block.
+        loop {
+            let started_after = app_state_twap.last_processed_block();
+            match receive_event(Arc::clone(&source), pair_ids.clone(), from_block, None, is_verbose).await {
+                Some(mut receiver) => {
+                    let app_state_storage = Arc::clone(&app_state_twap);
+                    let twap_storage_thread = tokio::spawn(async move {
+                        while let Some(event) = receiver.recv().await {
+                            match event {
+                                ListenerEvent::Received(transaction) => app_state_storage.update(transaction),
+                                ListenerEvent::Reorg { fork_block } => app_state_storage.rollback(fork_block),
+                                ListenerEvent::Processed { block_number } => app_state_storage.processed(block_number),
+                                ListenerEvent::Finalized { block_number } => app_state_storage.finalized(block_number),
+                                ListenerEvent::Pending(transactions) => app_state_storage.update_pending(transactions)
+                            }
+                        }
+                    });
+
+                    if let Err(e) = twap_storage_thread.await {
+                        eprintln!("❌ The TWAP storage task failed : {e}");
+                    }
+                    eprintln!("❌ The event listener stopped");
                 }
+                None => eprintln!("❌ Failed to start the event listener")
             }
-        });
 
-        let _ = twap_storage_thread.await;
+            if let Some(block_number) = app_state_twap.last_processed_block() {
+                app_state_twap.rollback(block_number);
+                from_block = Some(block_number + 1);
+            }
+            // A run which processed blocks was healthy, so occasional failures do not add up to the longest delay.
+            if app_state_twap.last_processed_block() > started_after {
+                restarts = 0;
+            }
+
+            let delay = restart_policy.delay(restarts);
+            restarts = restarts.saturating_add(1);
+            println!("🔁 Restarting the event listener in {delay:?}");
+            tokio::time::sleep(delay).await;
+        }
     });
 
-    let _ = restapi_thread.await;
-    let _ = gather_twap_thread.await;
+    let run = async {
+        let _ = restapi_thread.await;
+        let _ = gather_twap_thread.await;
+    };
+    tokio::select! {
+        _ = run => {}
+        _ = tokio::signal::ctrl_c() => app_state.shutdown()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::Value;
+
+    use std::sync::Arc;
+
+    use axum::{body::{to_bytes, Body}, http::Request};
+    use tower::util::ServiceExt;
+
+    use super::{server_run_forever, AppState, AppStateImpl};
+    use crate::server::restapi::create_restapi;
+    use crate::{
+        events::{source::{scripted_transaction, ScriptedSource}, transaction::Transaction},
+        metrics::periods::PairConfig
+    };
+
+    const PAIR_ID: &str = "BTC/USD";
+
+    #[tokio::test]
+    async fn server_run_forever_serves_prices_from_source() {
+        let with_volume = |mut transaction: Transaction, volume: u128| {
+            transaction.spot_entry.volume = volume;
+            transaction
+        };
+        let source = ScriptedSource::new();
+        source.push_block(vec![with_volume(scripted_transaction(PAIR_ID, 3600, 100), 2)]);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 3700, 120), scripted_transaction("ETH/USD", 3700, 7)]);
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let server = tokio::spawn(server_run_forever(
+            String::from("127.0.0.1"),
+            port.to_string(),
+            vec![PairConfig { twap_periods: vec![3600, 60], ..PairConfig::new(PAIR_ID) }, PairConfig::new("ETH/USD")],
+            source,
+            None,
+            None,
+            false
+        ));
+
+        let get_data = |path: &'static str| async move {
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if let Ok(response) = reqwest::get(format!("http://127.0.0.1:{port}{path}")).await {
+                    let body: Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+                    if !body["data"].is_null() {
+                        return body["data"].as_u64();
+                    }
+                }
+            }
+            None
+        };
+
+        assert_eq!(get_data("/data").await, Some(120));
+        assert_eq!(get_data("/data/BTC/USD").await, Some(120));
+        assert_eq!(get_data("/data/ETH/USD").await, Some(7));
+        // Only the first minute of the 1m TWAP is closed, by the second block.
+        assert_eq!(get_data("/twap/1m").await, Some(100));
+        assert_eq!(get_data("/twap/60/BTC/USD").await, Some(100));
+        // The last price is in effect over the whole window ending now.
+        assert_eq!(get_data("/rolling/1m").await, Some(120));
+        assert_eq!(get_data("/rolling/1h/ETH/USD").await, Some(7));
+        // The second entry has no volume, so only the first one weighs in the VWAP of the first minute.
+        assert_eq!(get_data("/vwap/1m").await, Some(100));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn server_run_forever_serves_finalized_value_after_confirmations() {
+        let source = ScriptedSource::new();
+        source.require_confirmations(1);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 3600, 100)]);
+        source.push_block(vec![scripted_transaction(PAIR_ID, 3700, 120)]);
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let server = tokio::spawn(server_run_forever(
+            String::from("127.0.0.1"),
+            port.to_string(),
+            vec![PairConfig::new(PAIR_ID)],
+            source.clone(),
+            None,
+            None,
+            false
+        ));
+
+        let get_body = |path: &'static str| async move {
+            let response = reqwest::get(format!("http://127.0.0.1:{port}{path}")).await.ok()?;
+            serde_json::from_str::<Value>(&response.text().await.ok()?).ok()
+        };
+        // Polls `path` until it serves `expected`.
+        let serves = |path: &'static str, expected: u64| async move {
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if get_body(path).await.is_some_and(|body| body["data"].as_u64() == Some(expected)) {
+                    return true;
+                }
+            }
+            false
+        };
+
+        assert!(serves("/data", 120).await);
+        assert!(serves("/finalized", 100).await);
+        assert_eq!(get_body("/status").await.unwrap()["last_finalized_block"].as_u64(), Some(1));
+
+        source.push_block(vec![]);
+        assert!(serves("/finalized/BTC/USD", 120).await);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn server_run_forever_reconciles_provisional_value() {
+        let source = ScriptedSource::new();
+        source.push_block(vec![scripted_transaction(PAIR_ID, 3600, 100), scripted_transaction(PAIR_ID, 3700, 120)]);
+        let pending_entry = scripted_transaction(PAIR_ID, 3800, 150);
+        source.set_pending(vec![pending_entry.clone()]);
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let server = tokio::spawn(server_run_forever(
+            String::from("127.0.0.1"),
+            port.to_string(),
+            vec![PairConfig::new(PAIR_ID)],
+            source.clone(),
+            None,
+            None,
+            false
+        ));
+
+        // Polls `path` until it serves `expected`.
+        let serves = |path: &'static str, expected: u64| async move {
+            for _ in 0..50 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if let Ok(response) = reqwest::get(format!("http://127.0.0.1:{port}{path}")).await {
+                    let body: Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+                    if body["data"].as_u64() == Some(expected) {
+                        return true;
+                    }
+                }
+            }
+            false
+        };
+
+        // The pending price weighs in the provisional TWAP of the current hour only.
+        assert!(serves("/provisional", 110).await);
+        assert!(serves("/data", 120).await);
+
+        // The pending event never makes it in, the provisional TWAP going back to the accepted one.
+        source.set_pending(vec![]);
+        source.push_block(vec![]);
+        assert!(serves("/provisional", 100).await);
+
+        // The pending event is accepted in the next block.
+        source.set_pending(vec![pending_entry.clone()]);
+        assert!(serves("/provisional/BTC/USD", 110).await);
+        source.set_pending(vec![]);
+        source.push_block(vec![pending_entry]);
+        assert!(serves("/data", 150).await);
+        assert!(serves("/provisional-twap/1h", 110).await);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn app_state_keeps_serving_after_a_panic_holding_its_locks() {
+        let app_state = Arc::new(AppStateImpl::new(vec![PairConfig::new(PAIR_ID)], None, false, Box::new(Vec::new)));
+        let in_block = |block_number: u64, transaction: Transaction| Transaction { block_number, ..transaction };
+        app_state.update(in_block(1, scripted_transaction(PAIR_ID, 3600, 100)));
+        app_state.processed(1);
+
+        // The storage task panics in the middle of a block, poisoning the locks it holds.
+        let failing_state = Arc::clone(&app_state);
+        let failure = std::thread::spawn(move || {
+            let _pending = failing_state.pending.lock().unwrap();
+            let _processed_block = failing_state.processed_block.lock().unwrap();
+            let _unfinalized = failing_state.unfinalized.lock().unwrap();
+            let _received_events = failing_state.received_events.lock().unwrap();
+            panic!("Simulated failure of the storage task");
+        });
+        assert!(failure.join().is_err());
+
+        // The supervisor retracts the partial block and restarts the listener after the last processed one.
+        app_state.rollback(1);
+        app_state.update(in_block(2, scripted_transaction(PAIR_ID, 3700, 120)));
+        app_state.processed(2);
+        assert_eq!(app_state.last_processed_block(), Some(2));
+
+        let restapi = create_restapi(app_state).await;
+        let response = restapi.oneshot(Request::builder().uri("/data").body(Body::default()).unwrap()).await.unwrap();
+        let body: Value = serde_json::from_slice(&to_bytes(response.into_body(), 1024).await.unwrap()).unwrap();
+        assert_eq!(body["data"].as_u64(), Some(120));
+    }
+
+    #[tokio::test]
+    async fn app_state_throttles_the_checkpoint_saves_until_shutdown() {
+        use crate::server::checkpoint::load_checkpoint;
+
+        let path = std::env::temp_dir().join(format!("twaplast-app-checkpoint-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let app_state =
+            AppStateImpl::new(vec![PairConfig::new(PAIR_ID)], Some(path.clone()), false, Box::new(Vec::new));
+        let in_block = |block_number: u64, transaction: Transaction| Transaction { block_number, ..transaction };
+        let saved_block = || load_checkpoint(&path).unwrap().map(|checkpoint| checkpoint.last_processed_block);
+
+        app_state.update(in_block(1, scripted_transaction(PAIR_ID, 3600, 100)));
+        app_state.processed(1);
+        assert_eq!(saved_block(), Some(1));
+        app_state.update(in_block(2, scripted_transaction(PAIR_ID, 3700, 120)));
+        app_state.processed(2);
+        assert_eq!(saved_block(), Some(1));
+
+        // The event of the partially processed block is read again on restart.
+        app_state.update(in_block(3, scripted_transaction(PAIR_ID, 3800, 130)));
+        app_state.shutdown();
+        assert_eq!(saved_block(), Some(2));
+        assert_eq!(app_state.get_last_value(PAIR_ID), Some(120));
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
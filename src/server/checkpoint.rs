@@ -0,0 +1,76 @@
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{events::transaction::Transaction, metrics::storage::StorageSnapshot};
+
+/// State persisted after each processed block so that a restart resumes where the listener stopped.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    pub(crate) last_processed_block: u64,
+    /// Storage of each followed pair, keyed by pair id.
+    pub(crate) storages: HashMap<String, StorageSnapshot>,
+    /// Last block whose events count towards the finalised storages.
+    #[serde(default)]
+    pub(crate) last_finalized_block: Option<u64>,
+    #[serde(default)]
+    pub(crate) finalized_storages: HashMap<String, StorageSnapshot>,
+    /// Events of the processed blocks that are not final yet.
+    #[serde(default)]
+    pub(crate) unfinalized: Vec<Transaction>
+}
+
+/// Writes the checkpoint next to `path` then renames it, so a crash never leaves a truncated file.
+pub(crate) fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) -> Result<(), String> {
+    let content = serde_json::to_string(checkpoint).map_err(|e| format!("Failed to serialize checkpoint: {e}"))?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, content).map_err(|e| format!("Failed to write {}: {e}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path).map_err(|e| format!("Failed to write {}: {e}", path.display()))
+}
+
+/// Returns `None` when no checkpoint has been saved yet.
+pub(crate) fn load_checkpoint(path: &Path) -> Result<Option<Checkpoint>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse checkpoint {}: {e}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {e}", path.display()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use std::collections::HashMap;
+
+    use super::{load_checkpoint, save_checkpoint, Checkpoint};
+    use crate::metrics::storage::{HashMapStorage, MetricStorage};
+
+    #[rstest]
+    fn checkpoint_save_then_load() {
+        let path = std::env::temp_dir().join(format!("twaplast-checkpoint-{}.json", std::process::id()));
+        assert!(load_checkpoint(&path).unwrap().is_none());
+
+        let storage = HashMapStorage::new(&[3600]);
+        storage.insert(1800, 100);
+        let storages = HashMap::from([(String::from("BTC/USD"), storage.snapshot())]);
+        let checkpoint = Checkpoint {
+            last_processed_block: 42,
+            storages,
+            last_finalized_block: None,
+            finalized_storages: HashMap::new(),
+            unfinalized: vec![]
+        };
+        save_checkpoint(&path, &checkpoint).unwrap();
+
+        let mut checkpoint = load_checkpoint(&path).unwrap().unwrap();
+        assert_eq!(checkpoint.last_processed_block, 42);
+        let restored = HashMapStorage::new(&[3600]);
+        restored.restore(checkpoint.storages.remove("BTC/USD").unwrap());
+        assert_eq!(restored.last(), Some(100));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
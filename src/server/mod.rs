@@ -1,3 +1,4 @@
 pub(crate) mod restapi;
 pub(crate) mod app;
+pub(crate) mod checkpoint;
 pub(crate) mod signing;
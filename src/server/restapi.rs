@@ -2,41 +2,307 @@ use std::sync::Arc;
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
 use serde_json::{json, Value};
 
+use crate::metrics::{periods::parse_period, twap::TwapValue};
 use crate::server::app::AppState;
-use crate::server::signing::get_signature;
+use crate::server::signing::{get_signature, SignedFields};
 
 pub(crate) async fn create_restapi(state: Arc<dyn AppState>) -> Router {
     Router::new()
         .route("/health", get(handler_health))
         .route("/data", get(handler_data))
+        .route("/data/*pair_id", get(handler_pair_data))
+        .route("/finalized", get(handler_finalized))
+        .route("/finalized/*pair_id", get(handler_pair_finalized))
+        .route("/provisional", get(handler_provisional))
+        .route("/provisional/*pair_id", get(handler_pair_provisional))
+        .route("/provisional-twap/:period", get(handler_period_provisional))
+        .route("/provisional-twap/:period/*pair_id", get(handler_pair_period_provisional))
+        .route("/twap", get(handler_twap))
+        .route("/twap/:period", get(handler_period_twap))
+        .route("/twap/:period/*pair_id", get(handler_pair_twap))
+        .route("/rolling/:window", get(handler_rolling_twap))
+        .route("/rolling/:window/*pair_id", get(handler_pair_rolling_twap))
+        .route("/vwap", get(handler_vwap))
+        .route("/vwap/:period", get(handler_period_vwap))
+        .route("/vwap/:period/*pair_id", get(handler_pair_vwap))
+        .route("/rolling-vwap/:window", get(handler_rolling_vwap))
+        .route("/rolling-vwap/:window/*pair_id", get(handler_pair_rolling_vwap))
+        .route("/status", get(handler_status))
+        .route("/providers", get(handler_providers))
         .fallback(handler_404)
         .with_state(state)
 }
 
-fn cat_u8_16_n_u8_8_to_u8_24(u_16: [u8; 16], u_8: [u8 ;8]) -> [u8; 24] {
-    let mut res = [0u8; 24];
-    res[..16].copy_from_slice(&u_16);
-    res[16..].copy_from_slice(&u_8);
-    res
+/// Serves the first followed pair.
+pub async fn handler_data(State(state): State<Arc<dyn AppState>>) -> Json<Value> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    let value = state.get_last_value(&pair_id);
+    signed_data(state, "data", &pair_id, 0, value, None)
 }
 
-pub async fn handler_data(State(state): State<Arc<dyn AppState>>) -> Json<Value> {
+pub async fn handler_pair_data(
+    State(state): State<Arc<dyn AppState>>,
+    Path(pair_id): Path<String>
+) -> Result<Json<Value>, StatusCode> {
+    if !state.pair_ids().contains(&pair_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let value = state.get_last_value(&pair_id);
+    Ok(signed_data(state, "data", &pair_id, 0, value, None))
+}
+
+/// Serves the first followed pair, from the events of the final blocks only.
+pub async fn handler_finalized(State(state): State<Arc<dyn AppState>>) -> Json<Value> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    let value = state.get_finalized_value(&pair_id);
+    signed_data(state, "finalized", &pair_id, 0, value, None)
+}
+
+pub async fn handler_pair_finalized(
+    State(state): State<Arc<dyn AppState>>,
+    Path(pair_id): Path<String>
+) -> Result<Json<Value>, StatusCode> {
+    if !state.pair_ids().contains(&pair_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let value = state.get_finalized_value(&pair_id);
+    Ok(signed_data(state, "finalized", &pair_id, 0, value, None))
+}
+
+/// Serves the TWAP of the current default period of the first followed pair, including the events of the pending
+/// block.
+pub async fn handler_provisional(State(state): State<Arc<dyn AppState>>) -> Result<Json<Value>, StatusCode> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    let period = *state.twap_periods(&pair_id).first().ok_or(StatusCode::NOT_FOUND)?;
+    let twap = state.get_provisional_twap(&pair_id, period);
+    Ok(signed_twap(state, "provisional-twap", &pair_id, period, twap))
+}
+
+pub async fn handler_pair_provisional(
+    State(state): State<Arc<dyn AppState>>,
+    Path(pair_id): Path<String>
+) -> Result<Json<Value>, StatusCode> {
+    if !state.pair_ids().contains(&pair_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let period = *state.twap_periods(&pair_id).first().ok_or(StatusCode::NOT_FOUND)?;
+    let twap = state.get_provisional_twap(&pair_id, period);
+    Ok(signed_twap(state, "provisional-twap", &pair_id, period, twap))
+}
+
+/// Serves the provisional TWAP of the first followed pair over `period`, e.g. `/provisional-twap/15m`.
+pub async fn handler_period_provisional(
+    State(state): State<Arc<dyn AppState>>,
+    Path(period): Path<String>
+) -> Result<Json<Value>, StatusCode> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    let period = configured_period(&state, &pair_id, &period)?;
+    let twap = state.get_provisional_twap(&pair_id, period);
+    Ok(signed_twap(state, "provisional-twap", &pair_id, period, twap))
+}
+
+pub async fn handler_pair_period_provisional(
+    State(state): State<Arc<dyn AppState>>,
+    Path((period, pair_id)): Path<(String, String)>
+) -> Result<Json<Value>, StatusCode> {
+    if !state.pair_ids().contains(&pair_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let period = configured_period(&state, &pair_id, &period)?;
+    let twap = state.get_provisional_twap(&pair_id, period);
+    Ok(signed_twap(state, "provisional-twap", &pair_id, period, twap))
+}
+
+/// Serves the TWAP of the default period of the first followed pair.
+pub async fn handler_twap(State(state): State<Arc<dyn AppState>>) -> Result<Json<Value>, StatusCode> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    let period = *state.twap_periods(&pair_id).first().ok_or(StatusCode::NOT_FOUND)?;
+    let twap = state.get_twap(&pair_id, period);
+    Ok(signed_twap(state, "twap", &pair_id, period, twap))
+}
+
+/// Serves the TWAP of the first followed pair over `period`, e.g. `/twap/15m`.
+pub async fn handler_period_twap(
+    State(state): State<Arc<dyn AppState>>,
+    Path(period): Path<String>
+) -> Result<Json<Value>, StatusCode> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    pair_twap(state, &pair_id, &period)
+}
+
+pub async fn handler_pair_twap(
+    State(state): State<Arc<dyn AppState>>,
+    Path((period, pair_id)): Path<(String, String)>
+) -> Result<Json<Value>, StatusCode> {
+    if !state.pair_ids().contains(&pair_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    pair_twap(state, &pair_id, &period)
+}
+
+fn pair_twap(state: Arc<dyn AppState>, pair_id: &str, period: &str) -> Result<Json<Value>, StatusCode> {
+    let period = configured_period(&state, pair_id, period)?;
+    let twap = state.get_twap(pair_id, period);
+    Ok(signed_twap(state, "twap", pair_id, period, twap))
+}
+
+/// The period in seconds of `period`, e.g. `15m`, when it is one of the TWAP periods of the pair.
+fn configured_period(state: &Arc<dyn AppState>, pair_id: &str, period: &str) -> Result<u64, StatusCode> {
+    let period = parse_period(period).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !state.twap_periods(pair_id).contains(&period) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(period)
+}
+
+/// A TWAP over `period`, signed as `/data` and followed by its period.
+fn signed_twap(
+    state: Arc<dyn AppState>,
+    kind: &str,
+    pair_id: &str,
+    period: u64,
+    twap: Option<TwapValue>
+) -> Json<Value> {
+    let (value, timestamp) = (twap.as_ref().map(|twap| twap.value), twap.map(|twap| twap.timestamp));
+    let Json(mut json_data) = signed_data(state, kind, pair_id, period, value, timestamp);
+    json_data["period"] = json!(period);
+    Json(json_data)
+}
+
+/// Serves the TWAP of the first followed pair over the `window` before now, e.g. `/rolling/30m`.
+pub async fn handler_rolling_twap(
+    State(state): State<Arc<dyn AppState>>,
+    Path(window): Path<String>
+) -> Result<Json<Value>, StatusCode> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    pair_rolling_twap(state, &pair_id, &window)
+}
+
+pub async fn handler_pair_rolling_twap(
+    State(state): State<Arc<dyn AppState>>,
+    Path((window, pair_id)): Path<(String, String)>
+) -> Result<Json<Value>, StatusCode> {
+    if !state.pair_ids().contains(&pair_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    pair_rolling_twap(state, &pair_id, &window)
+}
+
+/// The TWAP over the `window` before now, signed as `/data` and followed by its window, timestamped with the start of
+/// the time it actually covers. Only the TWAP periods are available as windows.
+fn pair_rolling_twap(state: Arc<dyn AppState>, pair_id: &str, window: &str) -> Result<Json<Value>, StatusCode> {
+    let window = parse_period(window).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !state.twap_periods(pair_id).contains(&window) {
+        return Err(StatusCode::NOT_FOUND);
+    }
     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let last_value = state.get_last_value();
-    let value_as_bytes = if let Some(value) = last_value {
-        value.to_ne_bytes()
-    } else {
-        [0u8; 16]
-    };
-    let full_message = cat_u8_16_n_u8_8_to_u8_24(value_as_bytes, now.to_ne_bytes());
-    let signature = get_signature(&full_message, state.passkey());
-    println!("📃 Requesting data... Sending {:?} ({now}) [{signature}]", last_value);
+    let twap = state.get_rolling_twap(pair_id, window, now);
+    let (value, covered_from) = (twap.as_ref().map(|twap| twap.value), twap.map(|twap| twap.timestamp));
+    let Json(mut json_data) = signed_data(state, "rolling-twap", pair_id, window, value, covered_from);
+    json_data["window"] = json!(window);
+    Ok(Json(json_data))
+}
+
+/// Serves the VWAP of the default period of the first followed pair.
+pub async fn handler_vwap(State(state): State<Arc<dyn AppState>>) -> Result<Json<Value>, StatusCode> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    let period = *state.twap_periods(&pair_id).first().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(signed_vwap(state, &pair_id, period))
+}
+
+/// Serves the VWAP of the first followed pair over `period`, e.g. `/vwap/15m`.
+pub async fn handler_period_vwap(
+    State(state): State<Arc<dyn AppState>>,
+    Path(period): Path<String>
+) -> Result<Json<Value>, StatusCode> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    pair_vwap(state, &pair_id, &period)
+}
+
+pub async fn handler_pair_vwap(
+    State(state): State<Arc<dyn AppState>>,
+    Path((period, pair_id)): Path<(String, String)>
+) -> Result<Json<Value>, StatusCode> {
+    if !state.pair_ids().contains(&pair_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    pair_vwap(state, &pair_id, &period)
+}
+
+fn pair_vwap(state: Arc<dyn AppState>, pair_id: &str, period: &str) -> Result<Json<Value>, StatusCode> {
+    let period = parse_period(period).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !state.twap_periods(pair_id).contains(&period) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(signed_vwap(state, pair_id, period))
+}
+
+/// The last closed VWAP over `period`, signed as `/data` and followed by its period.
+fn signed_vwap(state: Arc<dyn AppState>, pair_id: &str, period: u64) -> Json<Value> {
+    let vwap = state.get_vwap(pair_id, period);
+    let (value, timestamp) = (vwap.as_ref().map(|vwap| vwap.value), vwap.map(|vwap| vwap.timestamp));
+    let Json(mut json_data) = signed_data(state, "vwap", pair_id, period, value, timestamp);
+    json_data["period"] = json!(period);
+    Json(json_data)
+}
+
+/// Serves the VWAP of the first followed pair over the `window` before now, e.g. `/rolling-vwap/30m`.
+pub async fn handler_rolling_vwap(
+    State(state): State<Arc<dyn AppState>>,
+    Path(window): Path<String>
+) -> Result<Json<Value>, StatusCode> {
+    let pair_id = state.pair_ids().swap_remove(0);
+    pair_rolling_vwap(state, &pair_id, &window)
+}
+
+pub async fn handler_pair_rolling_vwap(
+    State(state): State<Arc<dyn AppState>>,
+    Path((window, pair_id)): Path<(String, String)>
+) -> Result<Json<Value>, StatusCode> {
+    if !state.pair_ids().contains(&pair_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    pair_rolling_vwap(state, &pair_id, &window)
+}
+
+/// The VWAP over the `window` before now, signed as `/data` and followed by its window, timestamped with the start of
+/// the time it actually covers.
+fn pair_rolling_vwap(state: Arc<dyn AppState>, pair_id: &str, window: &str) -> Result<Json<Value>, StatusCode> {
+    let window = parse_period(window).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if !state.twap_periods(pair_id).contains(&window) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let vwap = state.get_rolling_vwap(pair_id, window, now);
+    let (value, covered_from) = (vwap.as_ref().map(|vwap| vwap.value), vwap.map(|vwap| vwap.timestamp));
+    let Json(mut json_data) = signed_data(state, "rolling-vwap", pair_id, window, value, covered_from);
+    json_data["window"] = json!(window);
+    Ok(Json(json_data))
+}
+
+/// Signs `last_value` with the `kind` of value, the pair, the period or window it is computed over and the start of
+/// the time it covers, none of them for a price, as encoded by `SignedFields::to_bytes`.
+fn signed_data(
+    state: Arc<dyn AppState>,
+    kind: &str,
+    pair_id: &str,
+    period: u64,
+    last_value: Option<u128>,
+    timestamp: Option<u64>
+) -> Json<Value> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let fields = SignedFields { kind, pair_id, period, value: last_value, timestamp: timestamp.unwrap_or(0), now };
+    let signature = get_signature(&fields.to_bytes(), state.passkey());
+    println!("📃 Requesting {pair_id} {kind} data... Sending {:?} ({now}) [{signature}]", last_value);
 
     let json_data = json!({
+        "kind": kind,
+        "pair_id": pair_id,
         "data": last_value,
+        "timestamp": timestamp,
         "now": now,
         "signature": signature,
         "identifier": state.identifier()
@@ -45,6 +311,17 @@ pub async fn handler_data(State(state): State<Arc<dyn AppState>>) -> Json<Value>
     Json(json_data)
 }
 
+pub async fn handler_status(State(state): State<Arc<dyn AppState>>) -> Json<Value> {
+    Json(json!({
+        "last_processed_block": state.last_processed_block(),
+        "last_finalized_block": state.last_finalized_block()
+    }))
+}
+
+pub async fn handler_providers(State(state): State<Arc<dyn AppState>>) -> Json<Value> {
+    Json(json!(state.providers()))
+}
+
 async fn handler_404() -> impl IntoResponse {
     (StatusCode::NOT_FOUND, "The requested resource was not found")
 }
@@ -62,7 +339,7 @@ mod tests {
 
     use axum::http::Request;
     use serde_json::Value;
-    use crate::server::{restapi::create_restapi, app::AppStateMock};
+    use crate::server::{restapi::create_restapi, app::{AppState, AppStateMock}};
     use tower::util::ServiceExt;
     use axum::http::StatusCode;
 
@@ -96,4 +373,108 @@ mod tests {
             } 
         }
     }
+
+    #[tokio::test]
+    #[rstest]
+    #[case("/data/BTC/USD", StatusCode::OK)]
+    #[case("/data/ETH/USD", StatusCode::NOT_FOUND)]
+    #[case("/finalized", StatusCode::OK)]
+    #[case("/finalized/ETH/USD", StatusCode::NOT_FOUND)]
+    #[case("/provisional", StatusCode::OK)]
+    #[case("/provisional/BTC/USD", StatusCode::OK)]
+    #[case("/provisional/ETH/USD", StatusCode::NOT_FOUND)]
+    #[case("/provisional-twap/1h", StatusCode::OK)]
+    #[case("/provisional-twap/1h/BTC/USD", StatusCode::OK)]
+    #[case("/provisional-twap/5m/BTC/USD", StatusCode::NOT_FOUND)]
+    #[case("/twap", StatusCode::OK)]
+    #[case("/twap/1h", StatusCode::OK)]
+    #[case("/twap/3600/BTC/USD", StatusCode::OK)]
+    #[case("/twap/5m/BTC/USD", StatusCode::NOT_FOUND)]
+    #[case("/twap/1h/ETH/USD", StatusCode::NOT_FOUND)]
+    #[case("/twap/1w/BTC/USD", StatusCode::BAD_REQUEST)]
+    #[case("/rolling/1h", StatusCode::OK)]
+    #[case("/rolling/60m/BTC/USD", StatusCode::OK)]
+    #[case("/rolling/30m/BTC/USD", StatusCode::NOT_FOUND)]
+    #[case("/rolling/1h/ETH/USD", StatusCode::NOT_FOUND)]
+    #[case("/rolling/1w", StatusCode::BAD_REQUEST)]
+    #[case("/vwap", StatusCode::OK)]
+    #[case("/vwap/1h/BTC/USD", StatusCode::OK)]
+    #[case("/vwap/5m", StatusCode::NOT_FOUND)]
+    #[case("/vwap/1h/ETH/USD", StatusCode::NOT_FOUND)]
+    #[case("/rolling-vwap/3600", StatusCode::OK)]
+    #[case("/rolling-vwap/1h/ETH/USD", StatusCode::NOT_FOUND)]
+    #[case("/rolling-vwap/1w/BTC/USD", StatusCode::BAD_REQUEST)]
+    async fn pair_data_response(#[case] uri: &str, #[case] expected_status: StatusCode) {
+        let app_state = Arc::new(AppStateMock::new(Some(42)));
+        let restapi = create_restapi(app_state).await;
+
+        let response = restapi
+            .oneshot(Request::builder().uri(uri).body(Body::default()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), expected_status);
+    }
+
+    #[tokio::test]
+    #[rstest]
+    #[case("/data/BTC/USD", "data", 0)]
+    #[case("/twap", "twap", 3600)]
+    #[case("/twap/1h/BTC/USD", "twap", 3600)]
+    #[case("/provisional", "provisional-twap", 3600)]
+    #[case("/provisional/BTC/USD", "provisional-twap", 3600)]
+    #[case("/provisional-twap/1h", "provisional-twap", 3600)]
+    #[case("/rolling/1h", "rolling-twap", 3600)]
+    #[case("/vwap", "vwap", 3600)]
+    async fn response_signs_pair_and_period(#[case] uri: &str, #[case] kind: &str, #[case] period: u64) {
+        use axum::body::to_bytes;
+        use serde_json::{from_slice, from_value};
+        use crate::server::signing::{check_signature, SignedFields};
+
+        let app_state = Arc::new(AppStateMock::new(Some(42)));
+        let restapi = create_restapi(app_state).await;
+        let response = restapi
+            .oneshot(Request::builder().uri(uri).body(Body::default()).unwrap())
+            .await
+            .unwrap();
+        let body: Value = from_slice(&to_bytes(response.into_body(), 1024).await.unwrap()).unwrap();
+        let signature = from_value(body["signature"].clone()).unwrap();
+        let public_key = from_value(body["identifier"].clone()).unwrap();
+        assert_eq!(body["kind"].as_str(), Some(kind));
+
+        let fields = |kind, pair_id, period| SignedFields {
+            kind,
+            pair_id,
+            period,
+            value: body["data"].as_u64().map(u128::from),
+            timestamp: body["timestamp"].as_u64().unwrap_or(0),
+            now: body["now"].as_u64().unwrap()
+        };
+        assert!(check_signature(&fields(kind, "BTC/USD", period).to_bytes(), signature, &public_key));
+        assert!(!check_signature(&fields(kind, "ETH/USD", period).to_bytes(), signature, &public_key));
+        assert!(!check_signature(&fields(kind, "BTC/USD", period + 1).to_bytes(), signature, &public_key));
+        assert!(!check_signature(&fields("finalized", "BTC/USD", period).to_bytes(), signature, &public_key));
+        let mut later = fields(kind, "BTC/USD", period);
+        later.timestamp += 1;
+        assert!(!check_signature(&later.to_bytes(), signature, &public_key));
+    }
+
+    #[tokio::test]
+    async fn status_returns_last_processed_block() {
+        use axum::body::to_bytes;
+        use serde_json::from_slice;
+
+        let app_state = Arc::new(AppStateMock::new(None));
+        app_state.processed(12);
+        app_state.finalized(10);
+        let restapi = create_restapi(app_state).await;
+
+        let response = restapi
+            .oneshot(Request::builder().uri("/status").body(Body::default()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = from_slice(&to_bytes(response.into_body(), 1024).await.unwrap()).unwrap();
+        assert_eq!(body["last_processed_block"].as_u64(), Some(12));
+        assert_eq!(body["last_finalized_block"].as_u64(), Some(10));
+    }
 }
@@ -8,11 +8,49 @@ fn as_message(value: &[u8]) -> Message {
     Message::from_digest(digest.to_byte_array())
 }
 
+/// What the signature of a REST response covers.
+pub(crate) struct SignedFields<'a> {
+    /// The kind of value, e.g. `data` or `twap`.
+    pub(crate) kind: &'a str,
+    pub(crate) pair_id: &'a str,
+    /// The period or window of an average in seconds, 0 for a price.
+    pub(crate) period: u64,
+    pub(crate) value: Option<u128>,
+    /// Start of the time covered by an average, 0 for a price.
+    pub(crate) timestamp: u64,
+    /// When the response is signed, in seconds since the epoch.
+    pub(crate) now: u64
+}
+
+impl SignedFields<'_> {
+    /// The kind then the pair id, each one prefixed with its length as a `u32`, followed by the period, 1 and the
+    /// value as a `u128` or 0 without any value, the timestamp and `now`, every integer being big-endian.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        for text in [self.kind, self.pair_id] {
+            bytes.extend((text.len() as u32).to_be_bytes());
+            bytes.extend(text.as_bytes());
+        }
+        bytes.extend(self.period.to_be_bytes());
+        match self.value {
+            Some(value) => {
+                bytes.push(1);
+                bytes.extend(value.to_be_bytes());
+            }
+            None => bytes.push(0)
+        }
+        bytes.extend(self.timestamp.to_be_bytes());
+        bytes.extend(self.now.to_be_bytes());
+        bytes
+    }
+}
+
 pub(crate) fn get_signature(value: &[u8], secret_key: &secp256k1::SecretKey) -> Signature {
     secret_key.sign_ecdsa(as_message(value))
 }
 
 
+#[cfg(test)]
 pub(crate) fn check_signature(value: &[u8], signature: Signature, public_key: &secp256k1::PublicKey) -> bool {
     let message = as_message(value);
     signature.verify(&message, public_key).is_ok()
@@ -21,5 +59,5 @@ pub(crate) fn check_signature(value: &[u8], signature: Signature, public_key: &s
 
 pub(crate) fn generate_keys() -> (secp256k1::SecretKey, secp256k1::PublicKey) {
     let (secret_key, public_key) = generate_keypair(&mut rand::thread_rng());
-    return (secret_key, public_key)
+    (secret_key, public_key)
 }
\ No newline at end of file